@@ -1,27 +1,351 @@
 use scrypto::prelude::*;
 use std::cmp;
 
+mod oracle;
+mod random;
+
+/// Largest number of NFTs minted by one call of mint_nfts. Every NFT is a separate mint and its traits are
+/// copied into the component state for the rarity, so the cost grows with the trait data of the batch.
+/// Bigger batches risk going over the execution cost limit of a transaction, NFTs with many or long
+/// traits may need smaller batches. A full batch of NFTs with 8 traits each is covered by the tests.
+const MAX_MINT_BATCH: usize = 50;
+
+/// Default raise of a new english auction bid over the previous one, in basis points
+const MIN_BID_INCREMENT_BPS: u16 = 500;
+
+/// Seconds after which a blind purchase still waiting for its random number can be cancelled and refunded
+const BLIND_TICKET_TIMEOUT: i64 = 3600;
+
 #[derive(ScryptoSbor, PartialEq)]
 enum AuctionType {
     None,
     Fixed(Decimal),
-    Dutch(Decimal, Decimal, Epoch, u64)
+    Dutch(Decimal, Decimal, DecayCurve, Epoch, u64),
+    English(Decimal, Epoch, u64),
+    Sealed(Decimal, Epoch, u64, u64),
+    Vickrey(Decimal, Epoch, u64, u64),
+    Bonding(BondingCurve)
+}
+
+#[derive(ScryptoSbor, PartialEq, Clone)]
+pub enum DecayCurve {
+    /// Price decreases by the same amount every epoch
+    Linear(Decimal),
+    /// Price decreases by the same fraction every epoch
+    Exponential(Decimal),
+    /// Price is set by (epochs since start, price) points in increasing epoch order
+    Stepped(Vec<(u64, Decimal)>)
+}
+
+impl DecayCurve {
+    fn price(&self, initial: Decimal, elapsed: u64) -> Decimal {
+        match self {
+          DecayCurve::Linear(decrease) => initial - *decrease * elapsed,
+          DecayCurve::Exponential(rate) => initial * (Decimal::ONE - *rate).powi(elapsed as i64),
+          DecayCurve::Stepped(points) => points.iter()
+            .take_while(|(epoch, _)| *epoch <= elapsed)
+            .last()
+            .map(|(_, price)| *price)
+            .unwrap_or(initial)
+        }
+    }
+    
+    fn validate(&self, initial: Decimal, floor: Decimal, length: u64) {
+        assert!(floor >= Decimal::zero(), "the floor price can't be negative");
+        assert!(initial >= floor, "the initial price can't be lower than the floor price");
+        match self {
+          DecayCurve::Linear(decrease) => {
+            assert!(*decrease >= Decimal::zero(), "the price decrease can't be negative");
+          },
+          DecayCurve::Exponential(rate) => {
+            assert!(*rate >= Decimal::zero() && *rate < Decimal::ONE, "the decay rate must be between 0 and 1");
+          },
+          DecayCurve::Stepped(points) => {
+            let mut previous: Option<&(u64, Decimal)> = None;
+            for point in points.iter() {
+              assert!(point.1 <= initial, "a step price can't be higher than the initial price");
+              match previous {
+                Option::Some((epoch, price)) => {
+                  assert!(point.0 > *epoch, "steps must be sorted by epoch");
+                  assert!(point.1 <= *price, "step prices can't increase");
+                },
+                Option::None => {}
+              };
+              previous = Some(point);
+            }
+          }
+        };
+        // Every curve is decreasing so checking the end is enough
+        assert!(self.price(initial, length) >= floor, "the price would go below the floor before the end of the auction");
+    }
+}
+
+#[derive(ScryptoSbor, PartialEq, Clone)]
+pub enum BondingCurve {
+    /// Base price increased by the slope for every NFT sold
+    Linear(Decimal, Decimal),
+    /// Base price increased by the growth rate for every NFT sold
+    Exponential(Decimal, Decimal)
+}
+
+impl BondingCurve {
+    fn price(&self, sold: u64) -> Decimal {
+        match self {
+          BondingCurve::Linear(base, slope) => *base + *slope * sold,
+          BondingCurve::Exponential(base, growth) => *base * (Decimal::ONE + *growth).powi(sold as i64)
+        }
+    }
+    
+    fn validate(&self) {
+        let (base, increase) = match self {
+          BondingCurve::Linear(base, slope) => (base, slope),
+          BondingCurve::Exponential(base, growth) => (base, growth)
+        };
+        assert!(*base >= Decimal::zero(), "the base price can't be negative");
+        assert!(*increase >= Decimal::zero(), "a bonding curve price can't decrease");
+    }
+}
+
+#[derive(ScryptoSbor)]
+struct Bid {
+    amount: Decimal,
+    bidder: Global<Account>
+}
+
+#[derive(ScryptoSbor)]
+struct SealedBid {
+    commitment: Hash,
+    deposit: Decimal,
+    revealed: Option<Decimal>,
+    bidder: Global<Account>
+}
+
+/// One phase of the sale, e.g. OG then allowlist then public
+#[derive(ScryptoSbor)]
+struct SalePhase {
+    start: Instant,
+    end: Instant,
+    /// Fixed price of the phase, the auction type is used when none
+    price: Option<Decimal>,
+    whitelist_address: Option<ResourceAddress>,
+    whitelist_max: Option<u16>,
+    whitelist_counter: HashMap<NonFungibleLocalId, u16>
+}
+
+#[derive(ScryptoSbor, PartialEq, Clone)]
+pub enum Discount {
+    /// Part of the price taken off, in basis points
+    Percentage(u16),
+    /// Amount taken off the price, which can't go below zero
+    Fixed(Decimal)
+}
+
+/// Discount granted to the holders of a badge collection
+#[derive(ScryptoSbor)]
+struct DiscountRule {
+    /// Only these badges get the discount, every badge of the collection when none
+    ids: Option<Vec<NonFungibleLocalId>>,
+    discount: Discount,
+    max_uses: Option<u16>,
+    counter: HashMap<NonFungibleLocalId, u16>
+}
+
+impl DiscountRule {
+    /// Price after the discount of the badge, counting one more use for it
+    fn apply(&mut self, badge: &NonFungibleBucket, price: Decimal) -> Decimal {
+        assert!(badge.amount() == dec!(1), "only one discount badge can be presented");
+        let nft_id = badge.non_fungible_local_id();
+        match self.ids {
+          Option::Some(ref ids) if !ids.contains(&nft_id) => return price,
+          _ => {}
+        };
+        let counter = match self.counter.get(&nft_id) {
+          Option::Some(counter) => counter+1,
+          Option::None => 1
+        };
+        match self.max_uses {
+          Option::Some(max) => assert!(counter <= max, "this badge has already been used for all its discounts"),
+          Option::None => {}
+        };
+        self.counter.insert(nft_id, counter);
+        match self.discount {
+          Discount::Percentage(bps) => price * Decimal::from(10000 - bps) / dec!(10000),
+          Discount::Fixed(amount) if amount > price => Decimal::zero(),
+          Discount::Fixed(amount) => price - amount
+        }
+    }
+}
+
+/// Commitment of a sealed bid, computed by the bidder off-ledger with the same encoding.
+/// It is bound to the bidder and the NFT so that it can't be copied by another bidder.
+fn bid_commitment(bidder: ComponentAddress, id: &NonFungibleLocalId, amount: Decimal, salt: String) -> Hash {
+    hash(scrypto_encode(&(bidder, id.clone(), amount, salt)).unwrap())
+}
+
+/// Check the format of an IPFS content identifier: a base58 CIDv0 or a base32 CIDv1
+fn validate_cid(cid: &str) {
+    const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    const BASE32: &str = "abcdefghijklmnopqrstuvwxyz234567";
+    if cid.starts_with("Qm") {
+      assert!(cid.len() == 46, "a CIDv0 must be 46 characters long");
+      assert!(cid.chars().all(|c| BASE58.contains(c)), "a CIDv0 must be encoded in base58");
+    } else {
+      // The multibase prefix 'b' is followed by the version 1 which is encoded as 'a'
+      assert!(cid.starts_with("ba"), "the CID must be a CIDv0 or a base32 CIDv1");
+      assert!(cid.len() >= 10, "this CIDv1 is too short");
+      assert!(cid.chars().skip(1).all(|c| BASE32.contains(c)), "a CIDv1 must be encoded in lowercase base32");
+    }
+}
+
+/// Traits written in the legacy "key,value;key,value" format
+fn parse_traits(metadata: &str) -> Vec<(String, String)> {
+    if metadata.is_empty() {
+      return Vec::new();
+    }
+    metadata.split(';')
+      .map(|pair| {
+        let (key, value) = pair.split_once(',').expect("a trait must be written as key,value");
+        (key.trim().to_string(), value.trim().to_string())
+      })
+      .collect()
+}
+
+fn validate_traits(traits: &Vec<(String, String)>) {
+    for (index, (key, value)) in traits.iter().enumerate() {
+      assert!(!key.is_empty() && !value.is_empty(), "a trait must have a name and a value");
+      assert!(traits.iter().skip(index + 1).all(|(other, _)| other != key), "a trait can only be listed once");
+    }
+}
+
+/// Leaf of the Merkle tree committing to the revealed data of one NFT
+fn reveal_leaf(id: &NonFungibleLocalId, name: &String, url: &String, metadata: &String) -> Hash {
+    hash(scrypto_encode(&(id, name, url, metadata)).unwrap())
+}
+
+/// Root of a Merkle tree from a leaf and the sibling hashes up to the root, each pair is hashed in increasing order
+fn merkle_root(leaf: Hash, proof: &Vec<Hash>) -> Hash {
+    proof.iter().fold(leaf, |node, sibling| {
+      if node.0 <= sibling.0 {
+        hash(scrypto_encode(&(node, *sibling)).unwrap())
+      } else {
+        hash(scrypto_encode(&(*sibling, node)).unwrap())
+      }
+    })
 }
 
 #[derive(ScryptoSbor, NonFungibleData)]
 pub struct NFT {
+    #[mutable]
     name: String,
+    #[mutable]
     key_image_url: String,
-    metadata: String
+    /// Attributes of the NFT as (name, value) pairs
+    #[mutable]
+    traits: Vec<(String, String)>,
+    /// IPFS content identifier of the image when it is hosted on IPFS
+    #[mutable]
+    ipfs_cid: Option<String>
 }
 
-#[derive(ScryptoSbor, PartialEq)]
+/// Receipt of a uniform price dutch auction purchase, used to claim the rebate
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct PurchaseReceipt {
+    price_paid: Decimal
+}
+
+/// Receiver of a part of the payments, identified by its account or by a badge resource
+#[derive(ScryptoSbor, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Payee {
+    Account(ComponentAddress),
+    Badge(ResourceAddress)
+}
+
+/// Payments received in one currency
+#[derive(ScryptoSbor)]
+struct Till {
+    /// Vault that store the payments
+    vault: FungibleVault,
+    /// Vault of the platform fees
+    fees: FungibleVault,
+    /// Amount available to collect by the owner
+    amount_to_collect: Decimal,
+    /// Part of the payments each payee can claim, kept in the payment vault
+    balances: HashMap<Payee, Decimal>,
+    /// Payments of the blind purchases waiting for their NFT
+    held: FungibleVault
+}
+
+impl Till {
+    fn new(ccy_addr: ResourceAddress) -> Till {
+        Till {
+            vault: FungibleVault::new(ccy_addr),
+            fees: FungibleVault::new(ccy_addr),
+            amount_to_collect: dec!(0),
+            balances: HashMap::new(),
+            held: FungibleVault::new(ccy_addr)
+        }
+    }
+}
+
+/// Blind purchase waiting for its random number, the payment is booked once the NFT is drawn
+#[derive(ScryptoSbor)]
+struct BlindTicket {
+    buyer: Global<Account>,
+    /// Random source which has to call back, even if another one is set in the meantime
+    source: ComponentAddress,
+    ccy_addr: ResourceAddress,
+    price: Decimal,
+    fee: Decimal,
+    referrer: Option<ComponentAddress>,
+    requested_at: Instant
+}
+
+/// Oracle quoting the payment currencies in the reference unit of the prices, e.g. USD
+#[derive(ScryptoSbor)]
+struct PriceOracle {
+    /// Component with a `get_price(ResourceAddress) -> (Decimal, Instant)` method
+    component: Global<AnyComponent>,
+    /// Oldest quote accepted, in seconds
+    max_age: i64,
+    /// Largest change accepted from the previous quote, as a fraction of it
+    max_deviation: Decimal,
+    /// Last quote accepted for each currency
+    last_quotes: HashMap<ResourceAddress, Decimal>
+}
+
+/// Emitted for every sale with the part kept by the platform
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SaleEvent {
+    nft_ids: Vec<NonFungibleLocalId>,
+    ccy_addr: ResourceAddress,
+    price: Decimal,
+    fee: Decimal
+}
+
+#[derive(ScryptoSbor, PartialEq, Clone, Copy)]
 enum Status {
     NOTSTARTED,
     ONGOING,
     CLOSED
 }
 
+/// Check that the badge belongs to the whitelist and count `count` more purchases for it
+fn use_whitelist(address: ResourceAddress, max: Option<u16>, counters: &mut HashMap<NonFungibleLocalId, u16>, badge: Option<&NonFungibleBucket>, count: u16) {
+    let badge_bucket = badge.expect("the auction is using a whitelist, we expect a badge to be presented");
+    assert!(badge_bucket.resource_address() == address, "the badge doesn't belong to the whitelist collection");
+    assert!(badge_bucket.amount() == dec!(1), "only one whitelist badge can be presented");
+    let nft_id = badge_bucket.non_fungible_local_id();
+    let counter = match counters.get(&nft_id) {
+      Option::Some(counter) => counter+count,
+      Option::None => count
+    };
+    max.and_then(|max: u16| -> Option<u16> {
+      assert!(counter <= max, "this badge has already been used to buy all NFT it could");
+      None
+    });
+    counters.insert(nft_id, counter);
+}
+
 fn create_admin_badge(addr: ComponentAddress) -> FungibleBucket {
   ResourceBuilder::new_fungible(OwnerRole::None)
     .metadata(metadata! {
@@ -43,17 +367,84 @@ fn create_admin_badge(addr: ComponentAddress) -> FungibleBucket {
 }
 
 #[blueprint]
+#[events(SaleEvent)]
 mod nft_project {
     enable_method_auth! {
+      roles {
+          platform => updatable_by: [];
+      },
       methods {
           set_auction_fixed => restrict_to: [OWNER];
           set_auction_dutch => restrict_to: [OWNER];
+          set_auction_dutch_curve => restrict_to: [OWNER];
+          set_auction_english => restrict_to: [OWNER];
+          set_auction_sealed => restrict_to: [OWNER];
+          set_auction_vickrey => restrict_to: [OWNER];
+          set_auction_bonding => restrict_to: [OWNER];
+          set_uniform_price => restrict_to: [OWNER];
           set_whitelist => restrict_to: [OWNER];
+          set_bid_increment => restrict_to: [OWNER];
+          set_sale_window => restrict_to: [OWNER];
+          add_sale_phase => restrict_to: [OWNER];
           mint_nft => restrict_to: [OWNER];
+          mint_nft_in_tier => restrict_to: [OWNER];
+          mint_nft_ipfs => restrict_to: [OWNER];
+          mint_nft_with_traits => restrict_to: [OWNER];
+          mint_nfts => restrict_to: [OWNER];
+          mint_nfts_with_traits => restrict_to: [OWNER];
+          set_ipfs_gateway => restrict_to: [OWNER];
+          mint_placeholder => restrict_to: [OWNER];
+          set_reveal_commitment => restrict_to: [OWNER];
+          reveal => restrict_to: [OWNER];
+          update_key_image_url => restrict_to: [OWNER];
+          update_ipfs_cid => restrict_to: [OWNER];
+          update_metadata => restrict_to: [OWNER];
+          update_traits => restrict_to: [OWNER];
+          freeze_metadata => restrict_to: [OWNER];
+          set_price_tier => restrict_to: [OWNER];
+          set_nft_tier => restrict_to: [OWNER];
+          set_nft_price => restrict_to: [OWNER];
+          set_rarity_prices => restrict_to: [OWNER];
+          compute_rarity_ranks => restrict_to: [OWNER];
+          get_rarity => PUBLIC;
           start_auction => restrict_to: [OWNER];
           close_auction => restrict_to: [OWNER];
           collect_payments => restrict_to: [OWNER];
+          set_payees => restrict_to: [OWNER];
+          set_referral_commission => restrict_to: [OWNER];
+          add_referrer => restrict_to: [OWNER];
+          remove_referrer => restrict_to: [OWNER];
+          set_currency => restrict_to: [OWNER];
+          remove_currency => restrict_to: [OWNER];
+          set_oracle => restrict_to: [OWNER];
+          set_discount => restrict_to: [OWNER];
+          remove_discount => restrict_to: [OWNER];
+          remove_oracle => restrict_to: [OWNER];
+          claim_balance => PUBLIC;
+          claim_balance_with_badge => PUBLIC;
+          get_balance => PUBLIC;
+          collect_fees => restrict_to: [platform];
+          set_royalty => restrict_to: [OWNER];
+          enforce_royalties => restrict_to: [OWNER];
+          collect_royalties => restrict_to: [OWNER];
+          transfer_nft => PUBLIC;
+          get_fee_config => PUBLIC;
+          get_fees_collected => PUBLIC;
+          withdraw_unsold => restrict_to: [OWNER];
           buy_nft => PUBLIC;
+          buy_nfts => PUBLIC;
+          buy_random => PUBLIC;
+          fulfil_random => PUBLIC;
+          cancel_ticket => PUBLIC;
+          set_random_source => restrict_to: [OWNER];
+          place_bid => PUBLIC;
+          commit_bid => PUBLIC;
+          reveal_bid => PUBLIC;
+          settle => PUBLIC;
+          settle_all => PUBLIC;
+          claim_nft => PUBLIC;
+          claim_refund => PUBLIC;
+          claim_rebate => PUBLIC;
       }
     }
   
@@ -68,169 +459,1465 @@ mod nft_project {
         nft_available: HashMap<NonFungibleLocalId, bool>,
         /// Resource Manager
         resource_manager: ResourceManager,
-        /// Vault that store all payments
-        ccy_vault: FungibleVault,
-        /// Currency address
+        /// Payments, fees and balances of each accepted currency
+        tills: HashMap<ResourceAddress, Till>,
+        /// Base currency address, bids and royalties are paid with it
         ccy_addr: ResourceAddress,
+        /// Amount of each other accepted currency paid for one unit of the base currency
+        ccy_rates: HashMap<ResourceAddress, Decimal>,
+        /// Prices of the purchases are in the reference unit of the oracle when set
+        oracle: Option<PriceOracle>,
         /// Auction type
         auction_type: AuctionType,
         // Owner badge address
         owner_badge_address: ResourceAddress,
-        /// Amount available to collect
-        amount_to_collect: Decimal,
         // Whitelist NFT Address
         whitelist_address: Option<ResourceAddress>,
         // Whitelist max
         whitelist_max: Option<u16>,
         // Whitelist counter
         whitelist_counter: HashMap<NonFungibleLocalId, u16>,
+        /// Vault holding the bids until they are settled or refunded
+        escrow_vault: FungibleVault,
+        /// Highest bid for each NFT
+        top_bids: HashMap<NonFungibleLocalId, Bid>,
+        /// Raise of a new english auction bid over the previous one, in basis points
+        bid_increment_bps: u16,
+        /// Sealed bid committed by each bidder on each NFT
+        sealed_bids: KeyValueStore<(NonFungibleLocalId, ComponentAddress), SealedBid>,
+        /// Bidders of each NFT with a sealed bid, in commit order
+        sealed_bidders: HashMap<NonFungibleLocalId, Vec<ComponentAddress>>,
+        /// Vault for won NFTs that the winner account refused
+        claim_vault: NonFungibleVault,
+        /// Winner of each NFT waiting in the claim vault
+        unclaimed_nfts: HashMap<NonFungibleLocalId, Global<Account>>,
+        /// Refunds that the bidder account refused, kept in the escrow vault
+        unclaimed_funds: HashMap<ComponentAddress, Decimal>,
+        /// Dutch auction buyers all pay the last price, the difference is refunded
+        uniform_price: bool,
+        /// Lowest price paid in a uniform price dutch auction
+        clearing_price: Option<Decimal>,
+        /// NFTs sold in a uniform price dutch auction, grouped by referrer
+        uniform_sold: HashMap<Option<ComponentAddress>, Vec<NonFungibleLocalId>>,
+        /// Resource Manager of the purchase receipts
+        receipt_manager: ResourceManager,
+        /// Opening and closing time of the sale, auction lengths are counted in minutes when set
+        sale_window: Option<(Instant, Instant)>,
+        /// Ordered phases of the sale, the sale window covers all of them
+        phases: Vec<SalePhase>,
+        /// Price of each named tier, e.g. common, rare or legendary
+        price_tiers: HashMap<String, Decimal>,
+        /// Tier of the NFTs which have one
+        nft_tiers: HashMap<NonFungibleLocalId, String>,
+        /// Price of the NFTs which have their own, it takes precedence over the tier
+        nft_prices: HashMap<NonFungibleLocalId, Decimal>,
+        /// Discount rule of each badge collection
+        discounts: HashMap<ResourceAddress, DiscountRule>,
+        /// Component drawing the NFTs of the blind purchases
+        random_source: Option<ComponentAddress>,
+        /// Blind purchases waiting for their random number
+        blind_tickets: HashMap<u32, BlindTicket>,
+        /// Key of the next blind purchase
+        next_ticket: u32,
+        /// NFTs minted with placeholder data which haven't been revealed yet
+        unrevealed: HashSet<NonFungibleLocalId>,
+        /// Merkle root of the final data of the placeholders, checked for each revealed NFT
+        reveal_commitment: Option<Hash>,
+        /// The data of the NFTs can't be updated anymore
+        metadata_frozen: bool,
+        /// Number of NFTs having each (trait, value) pair
+        trait_counts: HashMap<(String, String), u64>,
+        /// Traits of each NFT, kept to compute the rarity
+        nft_traits: HashMap<NonFungibleLocalId, Vec<(String, String)>>,
+        /// Prices by rarity as (lowest rank, price) in increasing rank order
+        rarity_prices: Vec<(u64, Decimal)>,
+        /// Rank of each NFT, computed by compute_rarity_ranks
+        rarity_ranks: HashMap<NonFungibleLocalId, u64>,
+        /// The traits changed since the ranks were computed
+        ranks_stale: bool,
+        /// Number of NFTs sold through buy_nft
+        nft_sold: u64,
+        /// Platform fee in basis points of the price
+        fee_bps: u16,
+        /// Platform fee added to every sale
+        fee_flat: Decimal,
+        /// Royalty on secondary sales in basis points of the price
+        royalty_bps: u16,
+        /// Lowest royalty paid for each NFT transferred, whatever the declared price
+        royalty_min: Decimal,
+        /// Vault of the royalties paid on secondary sales
+        royalty_vault: FungibleVault,
+        /// NFTs can only be deposited through transfer_nft
+        royalties_enforced: bool,
+        /// Payees sharing the payments with their number of shares
+        payees: Vec<(Payee, u16)>,
+        /// Commission of the referrers in basis points of the payment, after the platform fee
+        referral_bps: u16,
+        /// Accounts allowed to refer buyers
+        referrers: HashSet<ComponentAddress>,
     }
 
     impl NftProject {
-        pub fn instantiate_component(ccy_addr: ResourceAddress, collection_name: String) -> (Global<NftProject>, FungibleBucket) {
+        /// The platform fee of every sale is `fee_bps` basis points of the price plus `fee_flat`, capped to the price
+        pub fn instantiate_component(ccy_addr: ResourceAddress, collection_name: String, platform_badge: ResourceAddress, fee_bps: u16, fee_flat: Decimal) -> (Global<NftProject>, FungibleBucket) {
+            assert!(fee_bps <= 10000, "the platform fee can't be more than 100%");
+            assert!(fee_flat >= Decimal::zero(), "the platform fee can't be negative");
             let (address_reservation, component_address) =
                 Runtime::allocate_component_address(NftProject::blueprint_id()); 
             let owner_badge = create_admin_badge(component_address);
             let resource_manager = ResourceBuilder::new_ruid_non_fungible::<NFT>(
                                     OwnerRole::Updatable(rule!(require(owner_badge.resource_address()))))
-                .metadata(metadata! { init { "name" => collection_name, locked; }} )
+                .metadata(metadata! {
+                    roles {
+                        metadata_setter => rule!(require(global_caller(component_address)) || require(owner_badge.resource_address()));
+                        metadata_setter_updater => rule!(require(owner_badge.resource_address()));
+                        metadata_locker => rule!(require(owner_badge.resource_address()));
+                        metadata_locker_updater => rule!(require(owner_badge.resource_address()));
+                    },
+                    init { "name" => collection_name, locked; }
+                })
                 .mint_roles(mint_roles! (
                     minter => rule!(require(global_caller(component_address)) || require(owner_badge.resource_address())); 
                     minter_updater => rule!(require(owner_badge.resource_address()));
                 ))
+                .deposit_roles(deposit_roles! (
+                    depositor => rule!(allow_all);
+                    depositor_updater => rule!(require(global_caller(component_address)) || require(owner_badge.resource_address()));
+                ))
+                .non_fungible_data_update_roles(non_fungible_data_update_roles! (
+                    non_fungible_data_updater => rule!(require(global_caller(component_address)));
+                    non_fungible_data_updater_updater => rule!(require(global_caller(component_address)));
+                ))
                 .create_with_no_initial_supply();
             let nft_addr = resource_manager.address();
+            let receipt_manager = ResourceBuilder::new_ruid_non_fungible::<PurchaseReceipt>(OwnerRole::None)
+                .metadata(metadata! { init { "name" => "Impahla - purchase receipt", locked; }} )
+                .mint_roles(mint_roles! (
+                    minter => rule!(require(global_caller(component_address)));
+                    minter_updater => rule!(deny_all);
+                ))
+                .burn_roles(burn_roles! (
+                    burner => rule!(require(global_caller(component_address)));
+                    burner_updater => rule!(deny_all);
+                ))
+                .create_with_no_initial_supply();
             let component = Self {
                     status: Status::NOTSTARTED,
                     nft_vault: NonFungibleVault::new(nft_addr),
                     nft_addr: nft_addr,
                     nft_available: HashMap::new(),
                     resource_manager: resource_manager,
-                    ccy_vault: FungibleVault::new(ccy_addr),
+                    tills: HashMap::from([(ccy_addr, Till::new(ccy_addr))]),
                     ccy_addr: ccy_addr,
+                    ccy_rates: HashMap::new(),
+                    oracle: None,
                     auction_type: AuctionType::None,
                     owner_badge_address: owner_badge.resource_address(),
-                    amount_to_collect: dec!(0),
                     whitelist_address: None,
                     whitelist_max: None,
-                    whitelist_counter: HashMap::new()
+                    whitelist_counter: HashMap::new(),
+                    escrow_vault: FungibleVault::new(ccy_addr),
+                    top_bids: HashMap::new(),
+                    bid_increment_bps: MIN_BID_INCREMENT_BPS,
+                    sealed_bids: KeyValueStore::new(),
+                    sealed_bidders: HashMap::new(),
+                    claim_vault: NonFungibleVault::new(nft_addr),
+                    unclaimed_nfts: HashMap::new(),
+                    unclaimed_funds: HashMap::new(),
+                    uniform_price: false,
+                    clearing_price: None,
+                    uniform_sold: HashMap::new(),
+                    receipt_manager: receipt_manager,
+                    sale_window: None,
+                    phases: Vec::new(),
+                    price_tiers: HashMap::new(),
+                    nft_tiers: HashMap::new(),
+                    nft_prices: HashMap::new(),
+                    discounts: HashMap::new(),
+                    random_source: None,
+                    blind_tickets: HashMap::new(),
+                    next_ticket: 0,
+                    unrevealed: HashSet::new(),
+                    reveal_commitment: None,
+                    metadata_frozen: false,
+                    trait_counts: HashMap::new(),
+                    nft_traits: HashMap::new(),
+                    rarity_prices: Vec::new(),
+                    rarity_ranks: HashMap::new(),
+                    ranks_stale: false,
+                    nft_sold: 0,
+                    fee_bps: fee_bps,
+                    fee_flat: fee_flat,
+                    royalty_bps: 0,
+                    royalty_min: dec!(0),
+                    royalty_vault: FungibleVault::new(ccy_addr),
+                    royalties_enforced: false,
+                    payees: Vec::new(),
+                    referral_bps: 0,
+                    referrers: HashSet::new()
                 }.instantiate();
             let prepared_comp = 
               component.prepare_to_globalize(OwnerRole::Fixed(rule!(require(owner_badge.resource_address()))))
+                       .roles(roles!(platform => rule!(require(platform_badge));))
                        .with_address(address_reservation);
             (prepared_comp.globalize(), owner_badge)
         }
         
         pub fn set_auction_fixed(&mut self, cost: Decimal) {
-            assert!(self.status == Status::NOTSTARTED, "cannot change auction type after auction has been started");
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change auction type after auction has been started");
             self.auction_type = AuctionType::Fixed(cost);
+            self.validate_auction();
         }
         
         pub fn set_auction_dutch(&mut self, initial_cost: Decimal, cost_decrease: Decimal, length: u64) {
-            assert!(self.status == Status::NOTSTARTED, "cannot change auction type after auction has been started");
-            self.auction_type = AuctionType::Dutch(initial_cost, cost_decrease, Runtime::current_epoch(), length);
+            self.set_auction_dutch_curve(initial_cost, Decimal::zero(), DecayCurve::Linear(cost_decrease), length);
+        }
+        
+        /// Dutch auction following `curve` for `length` epochs (minutes with a sale window), the price never goes below `floor`
+        pub fn set_auction_dutch_curve(&mut self, initial_cost: Decimal, floor: Decimal, curve: DecayCurve, length: u64) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change auction type after auction has been started");
+            curve.validate(initial_cost, floor, length);
+            self.auction_type = AuctionType::Dutch(initial_cost, floor, curve, Runtime::current_epoch(), length);
+            self.validate_auction();
+        }
+        
+        pub fn set_auction_english(&mut self, initial_cost: Decimal, length: u64) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change auction type after auction has been started");
+            self.auction_type = AuctionType::English(initial_cost, Runtime::current_epoch(), length);
+            self.validate_auction();
+        }
+        
+        /// Sealed bids are committed for `commit_length` epochs (minutes with a sale window) then revealed for `reveal_length`
+        pub fn set_auction_sealed(&mut self, reserve: Decimal, commit_length: u64, reveal_length: u64) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change auction type after auction has been started");
+            self.auction_type = AuctionType::Sealed(reserve, Runtime::current_epoch(), commit_length, reveal_length);
+            self.validate_auction();
+        }
+        
+        /// Same windows as a sealed bid auction, but the winner pays the second highest bid
+        pub fn set_auction_vickrey(&mut self, reserve: Decimal, commit_length: u64, reveal_length: u64) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change auction type after auction has been started");
+            self.auction_type = AuctionType::Vickrey(reserve, Runtime::current_epoch(), commit_length, reveal_length);
+            self.validate_auction();
+        }
+        
+        /// The price of the next NFT only depends on how many have already been sold
+        pub fn set_auction_bonding(&mut self, curve: BondingCurve) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change auction type after auction has been started");
+            curve.validate();
+            self.auction_type = AuctionType::Bonding(curve);
+            self.validate_auction();
+        }
+        
+        /// With a dutch auction, every buyer gets a receipt to claim back what was paid above the final price
+        pub fn set_uniform_price(&mut self, enabled: bool) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            assert!(!enabled || self.phases.iter().all(|phase| phase.price.is_none()), "uniform price can't be used with phase prices");
+            assert!(!enabled || self.discounts.is_empty(), "uniform price can't be used with discounts");
+            assert!(!enabled || self.is_dutch(), "uniform price is only available for dutch auctions");
+            assert!(!enabled || self.oracle.is_none(), "uniform price can't be used with a price oracle");
+            self.uniform_price = enabled;
+        }
+        
+        /// Create or update a tier, its price replaces the fixed price for the NFTs of the tier
+        pub fn set_price_tier(&mut self, tier: String, price: Decimal) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            assert!(price >= Decimal::zero(), "the price can't be negative");
+            self.price_tiers.insert(tier, price);
+        }
+        
+        pub fn set_nft_tier(&mut self, id: NonFungibleLocalId, tier: String) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            assert!(self.nft_available.contains_key(&id), "this NFT doesn't belong to the collection");
+            assert!(self.price_tiers.contains_key(&tier), "this tier doesn't exist");
+            self.nft_tiers.insert(id, tier);
+        }
+        
+        /// Give an NFT its own fixed price
+        pub fn set_nft_price(&mut self, id: NonFungibleLocalId, price: Decimal) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            assert!(self.nft_available.contains_key(&id), "this NFT doesn't belong to the collection");
+            assert!(price >= Decimal::zero(), "the price can't be negative");
+            self.nft_prices.insert(id, price);
+        }
+        
+        /// Give a discount to the holders of a badge collection, or only to some of its badges.
+        /// It replaces the previous rule of the collection, with `max_uses` counted for each badge.
+        pub fn set_discount(&mut self, badge_address: ResourceAddress, ids: Option<Vec<NonFungibleLocalId>>, discount: Discount, max_uses: Option<u16>) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            assert!(!self.uniform_price, "uniform price can't be used with discounts");
+            match discount {
+              Discount::Percentage(bps) => assert!(bps <= 10000, "the discount can't be more than 100%"),
+              Discount::Fixed(amount) => assert!(amount >= Decimal::zero(), "the discount can't be negative")
+            };
+            self.discounts.insert(badge_address, DiscountRule {
+              ids: ids,
+              discount: discount,
+              max_uses: max_uses,
+              counter: HashMap::new()
+            });
+        }
+        
+        pub fn remove_discount(&mut self, badge_address: ResourceAddress) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            assert!(self.discounts.remove(&badge_address).is_some(), "there is no discount for this badge");
         }
         
         pub fn set_whitelist(&mut self, address: ResourceAddress, max: u16) {
-            assert!(self.status == Status::NOTSTARTED, "cannot change whitelist after auction has been started");
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change whitelist after auction has been started");
             self.whitelist_address = Some(address);
             self.whitelist_max = Some(max);
         }
+        
+        /// Raise of a new english auction bid over the previous one, 5% by default
+        pub fn set_bid_increment(&mut self, bid_increment_bps: u16) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            self.bid_increment_bps = bid_increment_bps;
+        }
+
+        /// Purchases and bids are only accepted between `start` and `end`, the auction starts and closes by itself.
+        /// The auction lengths are then counted in minutes since `start` instead of epochs.
+        pub fn set_sale_window(&mut self, start: Instant, end: Instant) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the sale window after auction has been started");
+            assert!(start.seconds_since_unix_epoch < end.seconds_since_unix_epoch, "the sale window must end after it starts");
+            assert!(self.phases.is_empty(), "the sale window is defined by the sale phases");
+            self.sale_window = Some((start, end));
+            // The sale starts by itself with the window so it must be valid already
+            self.validate_sale();
+        }
+        
+        /// Add a phase after the existing ones, with its own price and whitelist.
+        /// Without a price the auction type is used, without a whitelist the phase is public.
+        pub fn add_sale_phase(&mut self, start: Instant, end: Instant, price: Option<Decimal>, whitelist_address: Option<ResourceAddress>, whitelist_max: Option<u16>) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the sale phases after auction has been started");
+            assert!(start.seconds_since_unix_epoch < end.seconds_since_unix_epoch, "the sale phase must end after it starts");
+            assert!(price.is_none() || !self.uniform_price, "uniform price can't be used with phase prices");
+            let opening = match self.phases.last() {
+              Option::Some(previous) => {
+                assert!(start.seconds_since_unix_epoch >= previous.end.seconds_since_unix_epoch, "a sale phase can't start before the previous one ends");
+                self.phases[0].start
+              },
+              Option::None => start
+            };
+            self.phases.push(SalePhase {
+              start: start,
+              end: end,
+              price: price,
+              whitelist_address: whitelist_address,
+              whitelist_max: whitelist_max,
+              whitelist_counter: HashMap::new()
+            });
+            self.sale_window = Some((opening, end));
+            self.validate_sale();
+        }
 
         pub fn start_auction(&mut self) {
-            assert!(self.status == Status::NOTSTARTED, "auction has been started already");
-            assert!(self.auction_type != AuctionType::None, "cannot start an auction if the auction type is not defined");
+            assert!(self.current_status() == Status::NOTSTARTED, "auction has been started already");
+            self.validate_sale();
             self.status = Status::ONGOING;
         }
         
-        pub fn close_auction(&mut self) -> Vec<Bucket> {
-            assert!(self.status == Status::ONGOING, "can't close an auction which is not ongoing");
-            self.status = Status::CLOSED;
+        /// Close the auction before the end of the sale window, unsold NFTs and payments are withdrawn afterwards.
+        /// Auctions taking bids can only be closed once their bidding or reveal window is over.
+        pub fn close_auction(&mut self) {
+            assert!(self.current_status() == Status::ONGOING, "can't close an auction which is not ongoing");
+            match self.auction_type {
+              AuctionType::English(_, start, length) => {
+                assert!(self.elapsed(start) >= length, "the bidding window is not over yet");
+              },
+              AuctionType::Sealed(_, start, commit_length, reveal_length) | AuctionType::Vickrey(_, start, commit_length, reveal_length) => {
+                assert!(self.elapsed(start) >= commit_length + reveal_length, "the reveal window is not over yet");
+              },
+              _ => {}
+            };
+            self.finalize();
+        }
+        
+        /// Take back the NFTs which haven't been sold once the auction is closed
+        pub fn withdraw_unsold(&mut self) -> NonFungibleBucket {
+            assert!(self.current_status() == Status::CLOSED, "unsold NFTs can only be withdrawn once the auction is closed");
+            assert!(self.blind_tickets.is_empty(), "blind purchases are still waiting for their NFT, stale ones can be cancelled");
+            self.finalize();
+            self.nft_vault.take_all()
+        }
+        
+        /// Buy one NFT, the referrer registered by the owner gets a commission on the payment.
+        /// `badge` is checked by the whitelist, the discount comes from `discount_badge` or else from `badge`.
+        pub fn buy_nft(&mut self, id: NonFungibleLocalId, payment: FungibleBucket, badge: Option<NonFungibleBucket>, discount_badge: Option<NonFungibleBucket>, referrer: Option<Global<Account>>) -> Vec<Bucket> {
+            self.purchase(vec![id], payment, badge, discount_badge, referrer)
+        }
+        
+        /// Buy several NFTs at once, each one is priced as if it was bought alone.
+        /// The whitelist cap applies to the total and nothing is bought if one of them can't be.
+        pub fn buy_nfts(&mut self, ids: Vec<NonFungibleLocalId>, payment: FungibleBucket, badge: Option<NonFungibleBucket>, discount_badge: Option<NonFungibleBucket>, referrer: Option<Global<Account>>) -> Vec<Bucket> {
+            assert!(!ids.is_empty(), "at least one NFT must be bought");
+            self.purchase(ids, payment, badge, discount_badge, referrer)
+        }
+        
+        /// The random source is called with `request_random(component, method, key)`
+        /// and must call back `fulfil_random(key, random_seed)` on this component.
+        /// The pending blind purchases keep waiting for the source they were requested from.
+        pub fn set_random_source(&mut self, source: ComponentAddress) {
+            self.random_source = Some(source);
+        }
+        
+        /// Pay for a random NFT of the unsold pool, it is sent to the buyer once the random source calls back.
+        /// The NFTs are all sold at the auction price, the prices of the tiers and NFTs don't apply.
+        pub fn buy_random(&mut self, mut payment: FungibleBucket, badge: Option<NonFungibleBucket>, discount_badge: Option<NonFungibleBucket>, buyer: Global<Account>, referrer: Option<Global<Account>>) -> Vec<Bucket> {
+            let source = self.random_source.expect("blind purchases need a random source");
+            assert!(!self.uniform_price, "uniform price can't be used with blind purchases");
             let mut ret = Vec::<Bucket>::new();
-            ret.push(self.nft_vault.take_all().into());
-            ret.push(self.ccy_vault.take_all().into());
-            self.amount_to_collect = dec!(0);
-            for (_, available) in self.nft_available.iter_mut() {
-              *available = false;
-            }
+            let phase_price = self.admit(badge.as_ref(), 1);
+            let referrer = referrer.map(|referrer| self.check_referrer(&referrer));
+            
+            let ccy_addr = payment.resource_address();
+            let rate = match self.oracle {
+              Option::Some(_) => self.oracle_rate(ccy_addr),
+              Option::None => self.rate(ccy_addr)
+            };
+            let base_cost = match phase_price {
+              Option::Some(price) => price,
+              Option::None => self.auction_price()
+            };
+            let current_cost = self.discounted(discount_badge.as_ref().or(badge.as_ref()), base_cost) * rate;
+            self.nft_sold += 1;
+            let fee = self.fee_for(current_cost, rate);
+            self.tills.get_mut(&ccy_addr).unwrap().held.put(payment.take(current_cost));
+            
+            // Reserve an NFT for the buyer until the random number comes back
+            let key = self.next_ticket;
+            self.next_ticket += 1;
+            self.blind_tickets.insert(key, BlindTicket {
+              buyer: buyer,
+              source: source,
+              ccy_addr: ccy_addr,
+              price: current_cost,
+              fee: fee,
+              referrer: referrer,
+              requested_at: Clock::current_time_rounded_to_minutes()
+            });
+            let source: Global<AnyComponent> = Global::from(source);
+            source.call::<_, ()>("request_random", &(Runtime::global_address(), "fulfil_random".to_string(), key));
+            
+            match badge {
+              Option::Some(badge) => ret.push(badge.into()),
+              Option::None => {}
+            };
+            match discount_badge {
+              Option::Some(badge) => ret.push(badge.into()),
+              Option::None => {}
+            };
+            ret.push(payment.into());
             return ret;
         }
         
-        pub fn buy_nft(&mut self, id: NonFungibleLocalId, mut payment: FungibleBucket, badge: Option<NonFungibleBucket>) -> Vec<Bucket> {
-            assert!(self.status == Status::ONGOING, "can't buy from an auction which is not ongoing");
+        /// Callback of the random source, draw the NFT of a blind purchase among the unsold ones
+        pub fn fulfil_random(&mut self, key: u32, random_seed: Vec<u8>) {
+            let ticket = self.blind_tickets.remove(&key).expect("there is no blind purchase with this key");
+            Runtime::assert_access_rule(rule!(require(global_caller(ticket.source))));
+            assert!(random_seed.len() >= 8, "the random seed is too short");
+            
+            // The NFTs still in the vault are the unsold ones, even once the auction is closed
+            let mut pool: Vec<NonFungibleLocalId> = self.nft_available.keys()
+              .filter(|id| self.nft_vault.contains_non_fungible(id))
+              .cloned()
+              .collect();
+            pool.sort();
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&random_seed[..8]);
+            let index = (u64::from_le_bytes(bytes) % pool.len() as u64) as usize;
+            let id = pool.swap_remove(index);
+            let proceeds = self.tills.get_mut(&ticket.ccy_addr).unwrap().held.take(ticket.price);
+            self.book_sale(vec![id.clone()], proceeds, ticket.fee, ticket.referrer);
+            self.deliver(id, ticket.buyer);
+        }
+        
+        /// Cancel a blind purchase whose random number didn't come back in time and refund the buyer.
+        /// A refund refused by the buyer account can be claimed with claim_balance.
+        pub fn cancel_ticket(&mut self, key: u32) {
+            let ticket = self.blind_tickets.remove(&key).expect("there is no blind purchase with this key");
+            let now = Clock::current_time_rounded_to_minutes().seconds_since_unix_epoch;
+            assert!(now >= ticket.requested_at.seconds_since_unix_epoch + BLIND_TICKET_TIMEOUT, "the blind purchase can't be cancelled yet");
+            self.nft_sold -= 1;
+            
+            let mut buyer = ticket.buyer;
+            let till = self.tills.get_mut(&ticket.ccy_addr).unwrap();
+            let refund = till.held.take(ticket.price);
+            match buyer.try_deposit_or_refund(refund.into(), None) {
+              Option::Some(refused) => {
+                let payee = Payee::Account(buyer.address());
+                let balance = till.balances.get(&payee).cloned().unwrap_or(dec!(0));
+                till.balances.insert(payee, balance + refused.amount());
+                till.vault.put(refused.as_fungible());
+              },
+              Option::None => {}
+            };
+        }
+        
+        /// Bid on one NFT of an english auction, the whole payment is the bid.
+        /// The previous top bidder is refunded, a new bid must raise it by the bid increment.
+        /// The whitelist and the sale phases apply to the bidders as to the buyers.
+        pub fn place_bid(&mut self, id: NonFungibleLocalId, payment: FungibleBucket, bidder: Global<Account>, badge: Option<NonFungibleBucket>) -> Option<NonFungibleBucket> {
+            assert!(self.current_status() == Status::ONGOING, "can't bid on an auction which is not ongoing");
+            let (initial, start, length) = match self.auction_type {
+              AuctionType::English(initial, start, length) => (initial, start, length),
+              _ => panic!("bids are only accepted by english auctions")
+            };
+            self.admit(badge.as_ref(), 0);
+            assert!(self.elapsed(start) < length, "the bidding window is over");
+            assert!(self.nft_available.get(&id) == Some(&true), "this NFT is not available");
+            assert!(payment.resource_address() == self.ccy_addr, "the bid must be paid in the auction currency");
+            
+            let amount = payment.amount();
+            let (top, minimum) = match self.top_bids.get(&id) {
+              Option::Some(bid) => (bid.amount, bid.amount * Decimal::from(10000 + self.bid_increment_bps as u32) / dec!(10000)),
+              Option::None => (Decimal::zero(), initial)
+            };
+            assert!(amount > top && amount >= minimum, "the bid must be at least {}", minimum);
+            
+            // Escrow the new bid and give the previous one back
+            self.escrow_vault.put(payment);
+            let previous = self.top_bids.insert(id, Bid { amount: amount, bidder: bidder });
+            match previous {
+              Option::Some(bid) => {
+                let refund = self.escrow_vault.take(bid.amount);
+                self.send_funds(bid.bidder, refund);
+              },
+              Option::None => {}
+            };
+            return badge;
+        }
+        
+        /// Commit a sealed bid with `bid_commitment(bidder, id, amount, salt)`.
+        /// The deposit must cover the bid and the reserve, it can be higher to hide the real amount.
+        /// A bidder has one sealed bid per NFT.
+        pub fn commit_bid(&mut self, id: NonFungibleLocalId, commitment: Hash, deposit: FungibleBucket, bidder: Global<Account>, badge: Option<NonFungibleBucket>) -> Option<NonFungibleBucket> {
+            assert!(self.current_status() == Status::ONGOING, "can't bid on an auction which is not ongoing");
+            let (reserve, start, commit_length) = match self.auction_type {
+              AuctionType::Sealed(reserve, start, commit_length, _) | AuctionType::Vickrey(reserve, start, commit_length, _) => (reserve, start, commit_length),
+              _ => panic!("sealed bids are only accepted by sealed bid auctions")
+            };
+            self.admit(badge.as_ref(), 0);
+            assert!(self.elapsed(start) < commit_length, "the commit window is over");
+            assert!(self.nft_available.get(&id) == Some(&true), "this NFT is not available");
+            assert!(deposit.resource_address() == self.ccy_addr, "the deposit must be paid in the auction currency");
+            assert!(deposit.amount() >= reserve, "the deposit must cover the reserve price");
+            let key = (id.clone(), bidder.address());
+            assert!(self.sealed_bids.get(&key).is_none(), "this account already has a sealed bid on this NFT");
+            
+            let bid = SealedBid {
+              commitment: commitment,
+              deposit: deposit.amount(),
+              revealed: None,
+              bidder: bidder
+            };
+            self.escrow_vault.put(deposit);
+            self.sealed_bidders.entry(id).or_insert(Vec::new()).push(key.1);
+            self.sealed_bids.insert(key, bid);
+            return badge;
+        }
+        
+        /// Reveal a sealed bid during the reveal window, unrevealed bids can't win and are refunded
+        pub fn reveal_bid(&mut self, id: NonFungibleLocalId, bidder: ComponentAddress, amount: Decimal, salt: String) {
+            let (start, commit_length, reveal_length) = match self.auction_type {
+              AuctionType::Sealed(_, start, commit_length, reveal_length) | AuctionType::Vickrey(_, start, commit_length, reveal_length) => (start, commit_length, reveal_length),
+              _ => panic!("only sealed bids can be revealed")
+            };
+            let elapsed = self.elapsed(start);
+            assert!(elapsed >= commit_length, "the reveal window is not opened yet");
+            assert!(elapsed < commit_length + reveal_length, "the reveal window is over");
+            
+            let commitment = bid_commitment(bidder, &id, amount, salt);
+            let mut bid = self.sealed_bids.get_mut(&(id, bidder)).expect("this account has no sealed bid on this NFT");
+            assert!(bid.revealed.is_none(), "this sealed bid is already revealed");
+            assert!(bid.commitment == commitment, "the sealed bid doesn't match this amount and salt");
+            assert!(amount <= bid.deposit, "the deposit doesn't cover the revealed bid");
+            bid.revealed = Some(amount);
+        }
+        
+        /// Give the NFT to the winner once the bidding window is over
+        pub fn settle(&mut self, id: NonFungibleLocalId) {
+            match self.auction_type {
+              AuctionType::English(_, start, length) => {
+                assert!(self.elapsed(start) >= length, "the bidding window is not over yet");
+                let bid = self.top_bids.remove(&id).expect("there is no bid to settle for this NFT");
+                self.award(id, bid);
+              },
+              AuctionType::Sealed(reserve, start, commit_length, reveal_length) => {
+                assert!(self.elapsed(start) >= commit_length + reveal_length, "the reveal window is not over yet");
+                assert!(self.sealed_bidders.contains_key(&id), "there is no bid to settle for this NFT");
+                self.settle_sealed(id, reserve, false);
+              },
+              AuctionType::Vickrey(reserve, start, commit_length, reveal_length) => {
+                assert!(self.elapsed(start) >= commit_length + reveal_length, "the reveal window is not over yet");
+                assert!(self.sealed_bidders.contains_key(&id), "there is no bid to settle for this NFT");
+                self.settle_sealed(id, reserve, true);
+              },
+              _ => panic!("only english and sealed bid auctions need to be settled")
+            };
+        }
+        
+        /// Settle every available NFT which received bids
+        pub fn settle_all(&mut self) {
+            let ids: Vec<NonFungibleLocalId> = self.nft_available.iter()
+              .filter(|(id, available)| **available && (self.top_bids.contains_key(*id) || self.sealed_bidders.contains_key(*id)))
+              .map(|(id, _)| id.clone())
+              .collect();
+            for id in ids {
+              self.settle(id);
+            }
+        }
+        
+        /// Retry the delivery of a won NFT that the winner account refused
+        pub fn claim_nft(&mut self, id: NonFungibleLocalId) {
+            let mut winner = self.unclaimed_nfts.remove(&id).expect("there is no NFT to claim with this id");
+            let nft = self.claim_vault.take_non_fungible(&id);
+            winner.try_deposit_or_abort(nft.into(), None);
+        }
+        
+        /// Retry the delivery of refunds that the bidder account refused
+        pub fn claim_refund(&mut self, mut bidder: Global<Account>) {
+            let amount = self.unclaimed_funds.remove(&bidder.address()).expect("there is no refund to claim for this account");
+            let refund = self.escrow_vault.take(amount);
+            bidder.try_deposit_or_abort(refund.into(), None);
+        }
+        
+        /// Refund the difference between the price paid and the clearing price once the auction is closed
+        pub fn claim_rebate(&mut self, receipts: NonFungibleBucket) -> FungibleBucket {
+            assert!(self.current_status() == Status::CLOSED, "rebates can only be claimed once the auction is closed");
+            self.finalize();
+            assert!(receipts.resource_address() == self.receipt_manager.address(), "these are not purchase receipts");
+            let clearing_price = self.clearing_price.unwrap();
+            let mut rebate = Decimal::zero();
+            for receipt in receipts.non_fungibles::<PurchaseReceipt>() {
+              let price_paid = receipt.data().price_paid;
+              if price_paid > clearing_price {
+                rebate = rebate + price_paid - clearing_price;
+              }
+            }
+            receipts.burn();
+            self.escrow_vault.take(rebate)
+        }
+        
+        fn purchase(&mut self, ids: Vec<NonFungibleLocalId>, mut payment: FungibleBucket, badge: Option<NonFungibleBucket>, discount_badge: Option<NonFungibleBucket>, referrer: Option<Global<Account>>) -> Vec<Bucket> {
             let mut ret = Vec::<Bucket>::new();
+            let count = u16::try_from(ids.len()).expect("too many NFTs in one purchase");
+            let phase_price = self.admit(badge.as_ref(), count);
             
-            // Do the whitelist logic if needed
-            match self.whitelist_address {
-              Option::Some(address) => {
-                let badge_bucket = badge.expect("the auction is using a whitelist, we expect a badge to be presented");
-                assert!(badge_bucket.resource_address() == address, "the badge doesn't belong to the whitelist collection");
-                let nft_id = badge_bucket.non_fungible_local_id();
-                let counter = match self.whitelist_counter.get(&nft_id) {
-                  Option::Some(counter) => counter+1,
-                  Option::None => 1
+            let ccy_addr = payment.resource_address();
+            let rate = match self.oracle {
+              Option::Some(_) => self.oracle_rate(ccy_addr),
+              Option::None => self.rate(ccy_addr)
+            };
+            let referrer = referrer.map(|referrer| self.check_referrer(&referrer));
+            let mut total_cost = Decimal::zero();
+            let mut total_fee = Decimal::zero();
+            for id in ids.iter() {
+              assert!(self.nft_available.get(id) == Some(&true), "this NFT is not available");
+              
+              // Deduce the current cost with the discount of the badge, converted to the payment currency
+              let base_cost = match phase_price {
+                Option::Some(price) => self.fixed_price(id, price),
+                Option::None => self.current_price(id)
+              };
+              let current_cost = self.discounted(discount_badge.as_ref().or(badge.as_ref()), base_cost) * rate;
+              self.nft_available.insert(id.clone(), false);
+              self.nft_sold += 1;
+              
+              // Take our price out of the payment bucket
+              if self.uniform_price {
+                assert!(ccy_addr == self.ccy_addr, "uniform price purchases must be paid in the base currency");
+                self.escrow_vault.put(payment.take(current_cost));
+                // The clearing price is the lowest price paid, so that no rebate can be negative
+                self.clearing_price = match self.clearing_price {
+                  Option::Some(price) if price < current_cost => Some(price),
+                  _ => Some(current_cost)
                 };
-                self.whitelist_max.and_then(|max: u16| -> Option<u16> {
-                  assert!(counter <= max, "this badge has already been used to buy all NFT it could");
-                  None
-                });
-                self.whitelist_counter.insert(nft_id, counter);
-                ret.push(badge_bucket.into());
-              },
+                self.uniform_sold.entry(referrer).or_insert(Vec::new()).push(id.clone());
+                ret.push(self.receipt_manager.mint_ruid_non_fungible(PurchaseReceipt { price_paid: current_cost }));
+              } else {
+                total_cost = total_cost + current_cost;
+                total_fee = total_fee + self.fee_for(current_cost, rate);
+              }
+            }
+            if !self.uniform_price {
+              self.book_sale(ids.clone(), payment.take(total_cost), total_fee, referrer);
+            }
+            
+            // Return the NFTs, the badges and the change
+            let nfts = self.nft_vault.take_non_fungibles(&ids.into_iter().collect());
+            ret.push(nfts.into());
+            match badge {
+              Option::Some(badge) => ret.push(badge.into()),
+              Option::None => {}
+            };
+            match discount_badge {
+              Option::Some(badge) => ret.push(badge.into()),
               Option::None => {}
             };
+            ret.push(payment.into());
+            return ret;
+        }
+        
+        /// Check that purchases of `count` NFTs are allowed and count them for the whitelist.
+        /// Bids are checked with a count of 0, the badge must be presented without using the whitelist cap.
+        /// Return the price of the current phase, if it has one.
+        fn admit(&mut self, badge: Option<&NonFungibleBucket>, count: u16) -> Option<Decimal> {
+            assert!(self.current_status() == Status::ONGOING, "can't buy from an auction which is not ongoing");
+            self.assert_sale_window();
+            let unsold = self.nft_available.values().filter(|available| **available).count();
+            assert!(unsold >= self.blind_tickets.len() + count as usize, "the remaining NFTs are reserved for blind purchases");
             
-            // Deduce the current cost
-            let current_cost = match self.auction_type {
+            // Do the whitelist logic if needed, the current phase replaces the global whitelist
+            let phase = self.current_phase();
+            match phase {
+              Option::Some(phase) => {
+                let phase = &mut self.phases[phase];
+                match phase.whitelist_address {
+                  Option::Some(address) => use_whitelist(address, phase.whitelist_max, &mut phase.whitelist_counter, badge, count),
+                  Option::None => {}
+                };
+                phase.price
+              },
+              Option::None => {
+                match self.whitelist_address {
+                  Option::Some(address) => use_whitelist(address, self.whitelist_max, &mut self.whitelist_counter, badge, count),
+                  Option::None => {}
+                };
+                None
+              }
+            }
+        }
+        
+        /// Price after the discount of the badge, if its collection has one
+        fn discounted(&mut self, badge: Option<&NonFungibleBucket>, price: Decimal) -> Decimal {
+            match badge {
+              Option::Some(badge) => {
+                match self.discounts.get_mut(&badge.resource_address()) {
+                  Option::Some(rule) => rule.apply(badge, price),
+                  Option::None => price
+                }
+              },
+              Option::None => price
+            }
+        }
+        
+        fn current_price(&self, id: &NonFungibleLocalId) -> Decimal {
+            match self.auction_type {
+              AuctionType::Fixed(cost) => self.fixed_price(id, cost),
+              _ => self.auction_price()
+            }
+        }
+        
+        /// Price of the auction without the prices of the tiers and NFTs
+        fn auction_price(&self) -> Decimal {
+            match self.auction_type {
               AuctionType::Fixed(cost) => cost,
-              AuctionType::Dutch(initial, decrease, start, length) => {
-                let diff = Runtime::current_epoch().number().checked_sub(start.number()).unwrap_or(0u64);
-                let mut cost = initial - decrease * cmp::min(length, diff);
-                if cost < Decimal::zero() {
-                  cost = Decimal::zero();
+              AuctionType::Dutch(initial, floor, ref curve, start, length) => {
+                let mut cost = curve.price(initial, cmp::min(length, self.elapsed(start)));
+                if cost < floor {
+                  cost = floor;
                 }
                 cost
               },
+              AuctionType::Bonding(ref curve) => curve.price(self.nft_sold),
+              AuctionType::English(_, _, _) => panic!("english auctions are sold through place_bid"),
+              AuctionType::Sealed(_, _, _, _) | AuctionType::Vickrey(_, _, _, _) => panic!("sealed bid auctions are sold through commit_bid"),
               AuctionType::None => panic!("Auction not started")
+            }
+        }
+        
+        /// Fixed price of one NFT: its own price, else its tier price, else its rarity price, else the collection price
+        fn fixed_price(&self, id: &NonFungibleLocalId, cost: Decimal) -> Decimal {
+            match (self.nft_prices.get(id), self.nft_tiers.get(id)) {
+              (Option::Some(price), _) => *price,
+              (Option::None, Option::Some(tier)) => *self.price_tiers.get(tier).unwrap(),
+              (Option::None, Option::None) if !self.rarity_prices.is_empty() => {
+                match self.rarity_ranks.get(id) {
+                  Option::Some(rank) => self.rarity_prices.iter()
+                    .find(|(lowest, _)| *rank <= *lowest)
+                    .map(|(_, price)| *price)
+                    .unwrap_or(cost),
+                  Option::None => cost
+                }
+              },
+              (Option::None, Option::None) => cost
+            }
+        }
+        
+        /// Index of the ongoing sale phase, if the sale has phases
+        fn current_phase(&self) -> Option<usize> {
+            if self.phases.is_empty() {
+              return None;
+            }
+            let phase = self.phases.iter().position(|phase|
+              Clock::current_time_is_at_or_after(phase.start, TimePrecision::Minute)
+                && Clock::current_time_is_strictly_before(phase.end, TimePrecision::Minute)
+            );
+            assert!(phase.is_some(), "no sale phase is ongoing");
+            phase
+        }
+        
+        /// Check that the referrer is registered, return its address
+        fn check_referrer(&self, referrer: &Global<Account>) -> ComponentAddress {
+            assert!(self.referrers.contains(&referrer.address()), "this referrer is not registered");
+            referrer.address()
+        }
+        
+        /// Status derived from the sale window, the owner can still start and close the auction by hand
+        fn current_status(&self) -> Status {
+            match (self.status, self.sale_window) {
+              (Status::CLOSED, _) => Status::CLOSED,
+              (status, Option::Some((opening, closing))) => {
+                if Clock::current_time_is_at_or_after(closing, TimePrecision::Minute) {
+                  Status::CLOSED
+                } else if Clock::current_time_is_at_or_after(opening, TimePrecision::Minute) {
+                  Status::ONGOING
+                } else {
+                  status
+                }
+              },
+              (status, Option::None) => status
+            }
+        }
+        
+        /// Settle everything left once the auction is closed, it is only done once
+        fn finalize(&mut self) {
+            if self.status == Status::CLOSED {
+              return;
+            }
+            self.status = Status::CLOSED;
+            self.settle_pending();
+            // Only the clearing price of the uniform price sales goes to the owner, the rest is kept for rebates.
+            // The sales of each referrer are booked at once, one booking per NFT would not fit in the cost limit.
+            match self.clearing_price {
+              Option::Some(price) => {
+                let sold = self.uniform_sold.clone();
+                for (referrer, ids) in sold {
+                  let count = Decimal::from(ids.len() as u64);
+                  let proceeds = self.escrow_vault.take(price * count);
+                  let fee = self.fee_for(price, Decimal::ONE) * count;
+                  self.book_sale(ids, proceeds, fee, referrer);
+                }
+              },
+              Option::None => {}
             };
+            for (_, available) in self.nft_available.iter_mut() {
+              *available = false;
+            }
+        }
+        
+        /// Time elapsed since `start`, in minutes since the opening when there is a sale window, in epochs otherwise
+        fn elapsed(&self, start: Epoch) -> u64 {
+            match self.sale_window {
+              Option::Some((opening, _)) => {
+                let now = Clock::current_time_rounded_to_minutes().seconds_since_unix_epoch;
+                (cmp::max(now - opening.seconds_since_unix_epoch, 0) / 60) as u64
+              },
+              Option::None => Runtime::current_epoch().number().checked_sub(start.number()).unwrap_or(0u64)
+            }
+        }
+        
+        fn assert_sale_window(&self) {
+            match self.sale_window {
+              Option::Some((opening, closing)) => {
+                assert!(Clock::current_time_is_at_or_after(opening, TimePrecision::Minute), "the sale is not opened yet");
+                assert!(Clock::current_time_is_strictly_before(closing, TimePrecision::Minute), "the sale is over");
+              },
+              Option::None => {}
+            };
+        }
+        
+        /// Checks of the auction configuration, the auction can't start without them.
+        /// A sale window starts the auction by itself so they are also run when the configuration changes.
+        fn validate_auction(&self) {
+            assert!(self.auction_type != AuctionType::None, "cannot start an auction if the auction type is not defined");
+            assert!(!self.uniform_price || self.is_dutch(), "uniform price is only available for dutch auctions");
+            // The sale window closes the auction, sealed bids revealed after it could be settled whenever the owner wants
+            match (&self.auction_type, self.sale_window) {
+              (AuctionType::Sealed(_, _, commit_length, reveal_length) | AuctionType::Vickrey(_, _, commit_length, reveal_length), Option::Some((opening, closing))) => {
+                let length = ((closing.seconds_since_unix_epoch - opening.seconds_since_unix_epoch) / 60) as u64;
+                assert!(commit_length + reveal_length <= length, "the reveal window must be over before the end of the sale window");
+              },
+              _ => {}
+            };
+        }
+        
+        /// Checks run when the sale is about to start
+        fn validate_sale(&self) {
+            self.validate_auction();
+            assert!(self.rarity_prices.is_empty() || !self.ranks_stale, "the rarity ranks must be computed before the sale");
+        }
+        
+        /// The sale window starts the sale by itself, the changes made after it is set must keep the sale valid
+        fn validate_scheduled_sale(&self) {
+            if self.sale_window.is_some() && self.current_status() == Status::NOTSTARTED {
+              self.validate_sale();
+            }
+        }
+        
+        fn is_dutch(&self) -> bool {
+            match self.auction_type {
+              AuctionType::Dutch(_, _, _, _, _) => true,
+              _ => false
+            }
+        }
+        
+        fn settle_pending(&mut self) {
+            let pending: Vec<NonFungibleLocalId> = self.top_bids.keys().cloned().collect();
+            for id in pending {
+              let bid = self.top_bids.remove(&id).unwrap();
+              self.award(id, bid);
+            }
+            let (reserve, second_price) = match self.auction_type {
+              AuctionType::Sealed(reserve, _, _, _) => (reserve, false),
+              AuctionType::Vickrey(reserve, _, _, _) => (reserve, true),
+              _ => (Decimal::zero(), false)
+            };
+            let pending: Vec<NonFungibleLocalId> = self.sealed_bidders.keys().cloned().collect();
+            for id in pending {
+              self.settle_sealed(id, reserve, second_price);
+            }
+        }
+        
+        fn settle_sealed(&mut self, id: NonFungibleLocalId, reserve: Decimal, second_price: bool) {
+            let bidders = self.sealed_bidders.remove(&id).unwrap_or(Vec::new());
+            let bids: Vec<SealedBid> = bidders.into_iter()
+              .map(|bidder| self.sealed_bids.remove(&(id.clone(), bidder)).unwrap())
+              .collect();
             
-            // Take the requested NFT
-            ret.push(self.nft_vault.take_non_fungible(&id).into());
-            self.nft_available.insert(id, false);
-
-            // Take our price out of the payment bucket
-            self.ccy_vault.put(payment.take(current_cost));
-            self.amount_to_collect = self.ccy_vault.amount();
-            ret.push(payment.into());
+            // Highest revealed bid wins, the earliest commitment wins a tie
+            let mut winner: Option<(usize, Decimal)> = None;
+            let mut second = reserve;
+            for (index, bid) in bids.iter().enumerate() {
+              match (bid.revealed, winner) {
+                (Option::Some(amount), Option::Some((_, best))) if amount > best => {
+                  second = best;
+                  winner = Some((index, amount));
+                },
+                (Option::Some(amount), Option::Some(_)) if amount > second => second = amount,
+                (Option::Some(amount), Option::None) if amount >= reserve => winner = Some((index, amount)),
+                _ => {}
+              };
+            }
+            // In a vickrey auction the winner only pays the second highest bid
+            if second_price {
+              winner = winner.map(|(index, _)| (index, second));
+            }
             
-            // Return the NFT and change
+            // Winner pays its bid, everything else goes back to the bidders
+            for (index, bid) in bids.into_iter().enumerate() {
+              let mut refund = bid.deposit;
+              match winner {
+                Option::Some((winner_index, amount)) if winner_index == index => {
+                  self.award(id.clone(), Bid { amount: amount, bidder: bid.bidder.clone() });
+                  refund = refund - amount;
+                },
+                _ => {}
+              };
+              if refund > Decimal::zero() {
+                let funds = self.escrow_vault.take(refund);
+                self.send_funds(bid.bidder, funds);
+              }
+            }
+        }
+        
+        fn award(&mut self, id: NonFungibleLocalId, bid: Bid) {
+            let proceeds = self.escrow_vault.take(bid.amount);
+            let fee = self.fee_for(bid.amount, Decimal::ONE);
+            self.book_sale(vec![id.clone()], proceeds, fee, None);
+            self.deliver(id, bid.bidder);
+        }
+        
+        /// Send an NFT of the vault to its buyer, it is kept in the claim vault if the account refuses it
+        fn deliver(&mut self, id: NonFungibleLocalId, mut winner: Global<Account>) {
+            let nft = self.nft_vault.take_non_fungible(&id);
+            self.nft_available.insert(id.clone(), false);
+            match winner.try_deposit_or_refund(nft.into(), None) {
+              Option::Some(refused) => {
+                self.claim_vault.put(refused.as_non_fungible());
+                self.unclaimed_nfts.insert(id, winner);
+              },
+              Option::None => {}
+            };
+        }
+        
+        /// Amount of an accepted currency paid for one unit of the base currency
+        fn rate(&self, ccy_addr: ResourceAddress) -> Decimal {
+            if ccy_addr == self.ccy_addr {
+              return Decimal::ONE;
+            }
+            *self.ccy_rates.get(&ccy_addr).expect("this currency is not accepted")
+        }
+        
+        /// Amount of an accepted currency paid for one reference unit, quoted by the oracle
+        fn oracle_rate(&mut self, ccy_addr: ResourceAddress) -> Decimal {
+            assert!(ccy_addr == self.ccy_addr || self.ccy_rates.contains_key(&ccy_addr), "this currency is not accepted");
+            let oracle = self.oracle.as_mut().unwrap();
+            let (price, quoted_at): (Decimal, Instant) = oracle.component.call("get_price", &(ccy_addr,));
+            assert!(price > Decimal::zero(), "the oracle price must be positive");
+            let expiry = quoted_at.add_seconds(oracle.max_age).unwrap();
+            assert!(Clock::current_time_is_strictly_before(expiry, TimePrecision::Minute), "the oracle price is stale");
+            match oracle.last_quotes.get(&ccy_addr) {
+              Option::Some(last) => {
+                let change = if price > *last { price - *last } else { *last - price };
+                assert!(change <= *last * oracle.max_deviation, "the oracle price moved too much since the last purchase");
+              },
+              Option::None => {}
+            };
+            oracle.last_quotes.insert(ccy_addr, price);
+            Decimal::ONE / price
+        }
+        
+        /// The flat fee is converted to the currency of the price with `rate`
+        fn fee_for(&self, price: Decimal, rate: Decimal) -> Decimal {
+            let fee = price * Decimal::from(self.fee_bps) / dec!(10000) + self.fee_flat * rate;
+            if fee > price {
+              price
+            } else {
+              fee
+            }
+        }
+        
+        /// Split the proceeds of a sale between the platform and the owner
+        fn book_sale(&mut self, nft_ids: Vec<NonFungibleLocalId>, mut proceeds: FungibleBucket, fee: Decimal, referrer: Option<ComponentAddress>) {
+            let price = proceeds.amount();
+            let ccy_addr = proceeds.resource_address();
+            let till = self.tills.get_mut(&ccy_addr).unwrap();
+            till.fees.put(proceeds.take(fee));
+            match referrer {
+              Option::Some(referrer) => {
+                let commission = proceeds.take(proceeds.amount() * Decimal::from(self.referral_bps) / dec!(10000));
+                let payee = Payee::Account(referrer);
+                let balance = till.balances.get(&payee).cloned().unwrap_or(dec!(0));
+                till.balances.insert(payee, balance + commission.amount());
+                till.vault.put(commission);
+              },
+              Option::None => {}
+            };
+            self.share_payment(proceeds);
+            Runtime::emit_event(SaleEvent { nft_ids: nft_ids, ccy_addr: ccy_addr, price: price, fee: fee });
+        }
+        
+        /// Credit each payee with its shares of the payment, the owner keeps what is left
+        fn share_payment(&mut self, payment: FungibleBucket) {
+            let amount = payment.amount();
+            let till = self.tills.get_mut(&payment.resource_address()).unwrap();
+            till.vault.put(payment);
+            let total_shares: u64 = self.payees.iter().map(|(_, shares)| *shares as u64).sum();
+            let mut shared = Decimal::zero();
+            for (payee, shares) in self.payees.iter() {
+              let part = amount * Decimal::from(*shares) / Decimal::from(total_shares);
+              let balance = till.balances.get(payee).cloned().unwrap_or(dec!(0));
+              till.balances.insert(*payee, balance + part);
+              shared = shared + part;
+            }
+            till.amount_to_collect = till.amount_to_collect + amount - shared;
+        }
+        
+        /// Take the balance of a payee out of every currency
+        fn take_balances(&mut self, payee: Payee) -> Vec<FungibleBucket> {
+            let mut ret = Vec::<FungibleBucket>::new();
+            for (_, till) in self.tills.iter_mut() {
+              match till.balances.remove(&payee) {
+                Option::Some(amount) => ret.push(till.vault.take(amount)),
+                Option::None => {}
+              };
+            }
             return ret;
         }
         
+        fn send_funds(&mut self, mut account: Global<Account>, funds: FungibleBucket) {
+            let amount = funds.amount();
+            match account.try_deposit_or_refund(funds.into(), None) {
+              Option::Some(refused) => {
+                self.escrow_vault.put(refused.as_fungible());
+                let owed = self.unclaimed_funds.get(&account.address()).cloned().unwrap_or(dec!(0));
+                self.unclaimed_funds.insert(account.address(), owed + amount);
+              },
+              Option::None => {}
+            };
+        }
+        
+        /// Mint an NFT with its traits in the "key,value;key,value" format
         pub fn mint_nft(&mut self, name: String, url: String, metadata: String) -> NonFungibleLocalId {
-            let new_nft = NFT {
+            self.mint_nft_with_traits(name, url, parse_traits(&metadata))
+        }
+        
+        pub fn mint_nft_with_traits(&mut self, name: String, url: String, traits: Vec<(String, String)>) -> NonFungibleLocalId {
+            self.mint(NFT {
                 name: name,
                 key_image_url: url,
-                metadata: metadata.clone(),
-            };
+                traits: traits,
+                ipfs_cid: None
+            })
+        }
+        
+        /// Mint up to `MAX_MINT_BATCH` NFTs given as (name, url, metadata), in the order of the returned ids
+        pub fn mint_nfts(&mut self, nfts: Vec<(String, String, String)>) -> Vec<NonFungibleLocalId> {
+            assert!(nfts.len() <= MAX_MINT_BATCH, "at most {} NFTs can be minted at once", MAX_MINT_BATCH);
+            nfts.into_iter()
+              .map(|(name, url, metadata)| self.mint_nft(name, url, metadata))
+              .collect()
+        }
+        
+        /// Mint up to `MAX_MINT_BATCH` NFTs given as (name, url, traits)
+        pub fn mint_nfts_with_traits(&mut self, nfts: Vec<(String, String, Vec<(String, String)>)>) -> Vec<NonFungibleLocalId> {
+            assert!(nfts.len() <= MAX_MINT_BATCH, "at most {} NFTs can be minted at once", MAX_MINT_BATCH);
+            nfts.into_iter()
+              .map(|(name, url, traits)| self.mint_nft_with_traits(name, url, traits))
+              .collect()
+        }
+        
+        /// Mint an NFT whose image is on IPFS, its url is `ipfs://<cid>`
+        pub fn mint_nft_ipfs(&mut self, name: String, cid: String, metadata: String) -> NonFungibleLocalId {
+            validate_cid(&cid);
+            self.mint(NFT {
+                name: name,
+                key_image_url: format!("ipfs://{}", cid),
+                traits: parse_traits(&metadata),
+                ipfs_cid: Some(cid)
+            })
+        }
+        
+        /// Gateway used by wallets to resolve the `ipfs://` images, e.g. https://ipfs.io/ipfs/
+        pub fn set_ipfs_gateway(&mut self, gateway: String) {
+            assert!(gateway.starts_with("https://"), "the gateway must be an https url");
+            self.resource_manager.set_metadata("ipfs_gateway", Url::of(gateway));
+        }
+        
+        fn mint(&mut self, new_nft: NFT) -> NonFungibleLocalId {
+            validate_traits(&new_nft.traits);
+            let traits = new_nft.traits.clone();
             let nft_bucket = self.resource_manager.mint_ruid_non_fungible(new_nft).as_non_fungible();
             let nft_id = nft_bucket.non_fungible_local_id();
             self.nft_available.insert(nft_id.clone(), true);
+            self.count_traits(&nft_id, traits);
             self.nft_vault.put(nft_bucket);
             nft_id
         }
+        
+        pub fn mint_nft_in_tier(&mut self, name: String, url: String, metadata: String, tier: String) -> NonFungibleLocalId {
+            assert!(self.price_tiers.contains_key(&tier), "this tier doesn't exist");
+            let nft_id = self.mint_nft(name, url, metadata);
+            self.nft_tiers.insert(nft_id.clone(), tier);
+            nft_id
+        }
+        
+        /// Mint an NFT whose real data is only set by `reveal` once the auction is closed
+        pub fn mint_placeholder(&mut self, name: String, url: String, metadata: String) -> NonFungibleLocalId {
+            let nft_id = self.mint_nft(name, url, metadata);
+            self.unrevealed.insert(nft_id.clone());
+            nft_id
+        }
+        
+        /// Commit before the sale to the data of the placeholders so the holders can check the reveal.
+        /// It is the root of a Merkle tree whose leaves are the `reveal_leaf` of each placeholder.
+        pub fn set_reveal_commitment(&mut self, commitment: Hash) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the reveal commitment after auction has been started");
+            self.reveal_commitment = Some(commitment);
+        }
+        
+        /// Set the real data of placeholders, sold or not, once the auction is closed, with the traits in the "key,value;key,value" format.
+        /// It can be done in several batches, each NFT comes with its Merkle proof when there is a commitment.
+        /// An image on IPFS comes with its CID, its url must then be `ipfs://<cid>`.
+        pub fn reveal(&mut self, nfts: Vec<(NonFungibleLocalId, String, String, String, Option<String>, Vec<Hash>)>) {
+            assert!(self.current_status() == Status::CLOSED, "NFTs can only be revealed once the auction is closed");
+            for (id, name, url, metadata, cid, proof) in nfts {
+              assert!(self.unrevealed.remove(&id), "this NFT is not waiting to be revealed");
+              match self.reveal_commitment {
+                Option::Some(commitment) => {
+                  let leaf = reveal_leaf(&id, &name, &url, &metadata);
+                  assert!(merkle_root(leaf, &proof) == commitment, "the revealed data doesn't match the commitment");
+                },
+                Option::None => {}
+              };
+              match cid {
+                Option::Some(cid) => {
+                  validate_cid(&cid);
+                  assert!(url == format!("ipfs://{}", cid), "the url of an image on IPFS must be ipfs://<cid>");
+                  self.resource_manager.update_non_fungible_data(&id, "ipfs_cid", Some(cid));
+                },
+                Option::None => {}
+              };
+              self.resource_manager.update_non_fungible_data(&id, "name", name);
+              self.resource_manager.update_non_fungible_data(&id, "key_image_url", url);
+              let traits = parse_traits(&metadata);
+              validate_traits(&traits);
+              self.count_traits(&id, traits.clone());
+              self.resource_manager.update_non_fungible_data(&id, "traits", traits);
+            }
+        }
+        
+        /// Fix the image of an NFT, sold or not, until the metadata is frozen.
+        /// The IPFS CID is cleared, an image on IPFS is set with `update_ipfs_cid`.
+        pub fn update_key_image_url(&mut self, id: NonFungibleLocalId, url: String) {
+            self.assert_updatable(&id);
+            self.resource_manager.update_non_fungible_data(&id, "key_image_url", url);
+            self.resource_manager.update_non_fungible_data(&id, "ipfs_cid", None::<String>);
+        }
+        
+        /// Fix the image of an NFT with an image on IPFS, its url becomes `ipfs://<cid>`
+        pub fn update_ipfs_cid(&mut self, id: NonFungibleLocalId, cid: String) {
+            self.assert_updatable(&id);
+            validate_cid(&cid);
+            self.resource_manager.update_non_fungible_data(&id, "key_image_url", format!("ipfs://{}", cid));
+            self.resource_manager.update_non_fungible_data(&id, "ipfs_cid", Some(cid));
+        }
+        
+        /// Replace the traits of an NFT, given in the "key,value;key,value" format
+        pub fn update_metadata(&mut self, id: NonFungibleLocalId, metadata: String) {
+            self.update_traits(id, parse_traits(&metadata));
+        }
+        
+        pub fn update_traits(&mut self, id: NonFungibleLocalId, traits: Vec<(String, String)>) {
+            self.assert_updatable(&id);
+            validate_traits(&traits);
+            self.count_traits(&id, traits.clone());
+            self.resource_manager.update_non_fungible_data(&id, "traits", traits);
+        }
+        
+        /// Rarity score of an NFT and its rank in the collection, the rarest NFT is ranked 1.
+        /// The score adds up the rarity of each trait: the number of NFTs divided by the number of NFTs sharing it.
+        pub fn get_rarity(&self, id: NonFungibleLocalId) -> (Decimal, u64) {
+            assert!(self.nft_traits.contains_key(&id), "this NFT doesn't belong to the collection");
+            assert!(!self.ranks_stale, "the rarity ranks must be computed first");
+            let rank = *self.rarity_ranks.get(&id).expect("the rarity ranks must be computed first");
+            (self.rarity_score(&id), rank)
+        }
+        
+        /// Rank every NFT by rarity, to be called once the traits are set as the ranks are used by the sale.
+        /// The ranks are frozen while the sale is ongoing, they can be computed again after a reveal.
+        pub fn compute_rarity_ranks(&mut self) {
+            assert!(self.current_status() != Status::ONGOING, "cannot change the pricing while the auction is ongoing");
+            let mut scores: Vec<(Decimal, NonFungibleLocalId)> = self.nft_traits.keys()
+              .map(|id| (self.rarity_score(id), id.clone()))
+              .collect();
+            scores.sort_by(|a, b| b.0.cmp(&a.0));
+            
+            // NFTs with the same score share the same rank
+            self.rarity_ranks.clear();
+            let mut rank = 0u64;
+            let mut previous: Option<Decimal> = None;
+            for (index, (score, id)) in scores.into_iter().enumerate() {
+              if previous != Some(score) {
+                rank = index as u64 + 1;
+                previous = Some(score);
+              }
+              self.rarity_ranks.insert(id, rank);
+            }
+            self.ranks_stale = false;
+        }
+        
+        /// Price the NFTs by rarity with (lowest rank, price) in increasing rank order,
+        /// e.g. the 10 rarest then the 100 rarest. Own prices and tiers take precedence.
+        pub fn set_rarity_prices(&mut self, prices: Vec<(u64, Decimal)>) {
+            assert!(self.current_status() == Status::NOTSTARTED, "cannot change the pricing after auction has been started");
+            let mut previous = 0u64;
+            for (rank, price) in prices.iter() {
+              assert!(*rank > previous, "rarity prices must be sorted by rank");
+              assert!(*price >= Decimal::zero(), "the price can't be negative");
+              previous = *rank;
+            }
+            self.rarity_prices = prices;
+            self.validate_scheduled_sale();
+        }
+        
+        /// Replace the traits of an NFT in the trait counts
+        fn count_traits(&mut self, id: &NonFungibleLocalId, traits: Vec<(String, String)>) {
+            match self.nft_traits.get(id) {
+              Option::Some(previous) => {
+                for pair in previous.iter() {
+                  let count = self.trait_counts.get_mut(pair).unwrap();
+                  *count -= 1;
+                }
+              },
+              Option::None => {}
+            };
+            for pair in traits.iter() {
+              *self.trait_counts.entry(pair.clone()).or_insert(0) += 1;
+            }
+            self.nft_traits.insert(id.clone(), traits);
+            self.ranks_stale = true;
+            self.validate_scheduled_sale();
+        }
+        
+        fn rarity_score(&self, id: &NonFungibleLocalId) -> Decimal {
+            let total = Decimal::from(self.nft_traits.len() as u64);
+            self.nft_traits.get(id).unwrap().iter()
+              .fold(Decimal::zero(), |score, pair| score + total / Decimal::from(*self.trait_counts.get(pair).unwrap()))
+        }
+        
+        /// Lock the data of every NFT for good, the update role of the resource is locked as well
+        pub fn freeze_metadata(&mut self) {
+            assert!(!self.metadata_frozen, "the metadata is already frozen");
+            assert!(self.unrevealed.is_empty(), "every placeholder must be revealed before freezing the metadata");
+            self.metadata_frozen = true;
+            self.resource_manager.set_updatable_non_fungible_data(rule!(deny_all));
+            self.resource_manager.lock_updatable_non_fungible_data();
+        }
+        
+        fn assert_updatable(&self, id: &NonFungibleLocalId) {
+            assert!(!self.metadata_frozen, "the metadata is frozen");
+            assert!(self.nft_available.contains_key(id), "this NFT doesn't belong to the collection");
+            assert!(!self.unrevealed.contains(id), "this NFT must be revealed before it can be updated");
+        }
         /*
         pub fn add_nft(&mut self, nft_bucket: NonFungibleBucket) {
-            assert!(self.status == Status::NOTSTARTED, "can't add an NFT to an auction which is not ongoing");
+            assert!(self.current_status() == Status::NOTSTARTED, "can't add an NFT to an auction which is not ongoing");
             for nft_id in nft_bucket.non_fungible_local_ids() {
               self.nft_available.insert(nft_id.clone(), true);
             }
             self.nft_vault.put(nft_bucket);
         }
         */
-        pub fn collect_payments(&mut self) -> FungibleBucket {
-            self.amount_to_collect = dec!(0);
-            self.ccy_vault.take_all()
+        /// Collect the payments of every accepted currency
+        pub fn collect_payments(&mut self) -> Vec<FungibleBucket> {
+            if self.current_status() == Status::CLOSED {
+              self.finalize();
+            }
+            let mut ret = Vec::<FungibleBucket>::new();
+            for (_, till) in self.tills.iter_mut() {
+              let amount = till.amount_to_collect;
+              till.amount_to_collect = dec!(0);
+              ret.push(till.vault.take(amount));
+            }
+            return ret;
+        }
+        
+        /// Accept payments in another currency, `rate` is the amount of it paid for one unit of the base currency.
+        /// The rate can be updated during the sale.
+        pub fn set_currency(&mut self, ccy_addr: ResourceAddress, rate: Decimal) {
+            assert!(ccy_addr != self.ccy_addr, "the base currency is always accepted");
+            assert!(rate > Decimal::zero(), "the conversion rate must be positive");
+            self.ccy_rates.insert(ccy_addr, rate);
+            self.tills.entry(ccy_addr).or_insert_with(|| Till::new(ccy_addr));
+        }
+        
+        /// Stop accepting a currency, what was paid with it can still be collected
+        pub fn remove_currency(&mut self, ccy_addr: ResourceAddress) {
+            assert!(self.ccy_rates.remove(&ccy_addr).is_some(), "this currency is not accepted");
+        }
+        
+        /// Price the purchases in the reference unit of an oracle, which replaces the conversion rates.
+        /// A quote older than `max_age` seconds or further than `max_deviation` from the previous one is refused,
+        /// setting the oracle again forgets the previous quotes.
+        pub fn set_oracle(&mut self, oracle: ComponentAddress, max_age: i64, max_deviation: Decimal) {
+            assert!(max_age > 0, "the maximum age of a quote must be positive");
+            assert!(max_deviation >= Decimal::zero(), "the maximum deviation can't be negative");
+            assert!(!self.uniform_price, "a price oracle can't be used with uniform price");
+            self.oracle = Some(PriceOracle {
+              component: Global::from(oracle),
+              max_age: max_age,
+              max_deviation: max_deviation,
+              last_quotes: HashMap::new()
+            });
+        }
+        
+        pub fn remove_oracle(&mut self) {
+            assert!(self.oracle.is_some(), "there is no oracle to remove");
+            self.oracle = None;
+        }
+        
+        /// Share the next payments between the payees, the owner keeps the payments if there are none
+        pub fn set_payees(&mut self, payees: Vec<(Payee, u16)>) {
+            for (index, (payee, shares)) in payees.iter().enumerate() {
+              assert!(*shares > 0, "a payee must have at least one share");
+              assert!(payees.iter().skip(index + 1).all(|(other, _)| other != payee), "a payee can only be listed once");
+            }
+            self.payees = payees;
+        }
+        
+        pub fn set_referral_commission(&mut self, referral_bps: u16) {
+            assert!(referral_bps <= 10000, "the commission can't be more than 100%");
+            self.referral_bps = referral_bps;
+        }
+        
+        /// Allow an account to refer buyers and get the referral commission
+        pub fn add_referrer(&mut self, account: ComponentAddress) {
+            assert!(self.referrers.insert(account), "this referrer is already registered");
+        }
+        
+        pub fn remove_referrer(&mut self, account: ComponentAddress) {
+            assert!(self.referrers.remove(&account), "this referrer is not registered");
+        }
+        
+        /// Send its part of the payments or its commissions to a payee account
+        pub fn claim_balance(&mut self, mut account: Global<Account>) {
+            let funds = self.take_balances(Payee::Account(account.address()));
+            assert!(!funds.is_empty(), "there is nothing to claim for this account");
+            for bucket in funds {
+              account.try_deposit_or_abort(bucket.into(), None);
+            }
+        }
+        
+        /// Withdraw its part of the payments in every currency for a payee identified by a badge
+        pub fn claim_balance_with_badge(&mut self, badge: Proof) -> Vec<FungibleBucket> {
+            // Any badge resource can be a payee, the proof is checked by hand
+            let badge = badge.skip_checking();
+            assert!(badge.amount() >= dec!(1), "the proof must contain the badge");
+            let funds = self.take_balances(Payee::Badge(badge.resource_address()));
+            assert!(!funds.is_empty(), "there is nothing to claim for this badge");
+            funds
+        }
+        
+        pub fn get_balance(&self, payee: Payee, ccy_addr: ResourceAddress) -> Decimal {
+            self.tills.get(&ccy_addr)
+              .and_then(|till| till.balances.get(&payee).cloned())
+              .unwrap_or(dec!(0))
+        }
+        
+        /// The royalty is `royalty_bps` of the payment but at least `royalty_min` for each NFT,
+        /// the price is declared by the parties so the minimum is what enforcement guarantees.
+        pub fn set_royalty(&mut self, royalty_bps: u16, royalty_min: Decimal) {
+            assert!(!self.royalties_enforced, "cannot change the royalty once it is enforced");
+            assert!(royalty_bps <= 10000, "the royalty can't be more than 100%");
+            assert!(royalty_min >= Decimal::zero(), "the minimum royalty can't be negative");
+            self.royalty_bps = royalty_bps;
+            self.royalty_min = royalty_min;
+        }
+        
+        /// Once the primary sale is closed, restrict the deposits of the NFTs so they can only
+        /// change hands through transfer_nft, which pays the royalty.
+        pub fn enforce_royalties(&mut self) {
+            assert!(self.current_status() == Status::CLOSED, "royalties can only be enforced once the auction is closed");
+            assert!(!self.royalties_enforced, "royalties are already enforced");
+            assert!(self.royalty_min > Decimal::zero(), "royalties can't be enforced without a minimum royalty");
+            self.royalties_enforced = true;
+            self.resource_manager.set_depositable(rule!(require(global_caller(Runtime::global_address())) || require(self.owner_badge_address)));
+        }
+        
+        pub fn collect_royalties(&mut self) -> FungibleBucket {
+            self.royalty_vault.take_all()
+        }
+        
+        /// Secondary sale of NFTs of the collection, the royalty is taken out of the payment,
+        /// the NFTs are deposited to the recipient and the rest of the payment is returned for the seller.
+        pub fn transfer_nft(&mut self, nft: NonFungibleBucket, mut payment: FungibleBucket, mut recipient: Global<Account>) -> FungibleBucket {
+            assert!(nft.resource_address() == self.nft_addr, "this NFT doesn't belong to the collection");
+            assert!(payment.resource_address() == self.ccy_addr, "the payment must be in the collection currency");
+            let minimum = self.royalty_min * nft.amount();
+            let mut royalty = payment.amount() * Decimal::from(self.royalty_bps) / dec!(10000);
+            if royalty < minimum {
+              royalty = minimum;
+            }
+            assert!(payment.amount() >= royalty, "the payment doesn't cover the minimum royalty of {}", minimum);
+            self.royalty_vault.put(payment.take(royalty));
+            recipient.try_deposit_or_abort(nft.into(), None);
+            payment
+        }
+        
+        pub fn collect_fees(&mut self) -> Vec<FungibleBucket> {
+            self.tills.iter_mut().map(|(_, till)| till.fees.take_all()).collect()
+        }
+        
+        /// Platform fee in basis points and flat amount
+        pub fn get_fee_config(&self) -> (u16, Decimal) {
+            (self.fee_bps, self.fee_flat)
+        }
+        
+        pub fn get_fees_collected(&self, ccy_addr: ResourceAddress) -> Decimal {
+            self.tills.get(&ccy_addr).map(|till| till.fees.amount()).unwrap_or(dec!(0))
         }
     }
 }
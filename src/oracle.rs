@@ -0,0 +1,30 @@
+use scrypto::prelude::*;
+
+/// Price oracle stub used by the tests, anyone can set the prices
+#[blueprint]
+mod mock_oracle {
+    struct MockOracle {
+        /// Price of one unit of each currency in the reference unit, with the time it was set
+        prices: HashMap<ResourceAddress, (Decimal, Instant)>
+    }
+
+    impl MockOracle {
+        pub fn instantiate() -> Global<MockOracle> {
+            Self {
+                    prices: HashMap::new()
+                }
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .globalize()
+        }
+
+        pub fn set_price(&mut self, ccy_addr: ResourceAddress, price: Decimal) {
+            self.prices.insert(ccy_addr, (price, Clock::current_time_rounded_to_minutes()));
+        }
+
+        /// Price of one unit of the currency in the reference unit and the time it was quoted
+        pub fn get_price(&self, ccy_addr: ResourceAddress) -> (Decimal, Instant) {
+            *self.prices.get(&ccy_addr).expect("there is no price for this currency")
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use scrypto::prelude::*;
+
+/// Random source stub used by the tests, the random bytes only depend on the order of the requests
+#[blueprint]
+mod mock_random {
+    struct MockRandom {
+        /// Requests waiting for their random bytes: component, method and key
+        pending: Vec<(ComponentAddress, String, u32)>,
+        /// Number of requests fulfilled, used as the seed of the next one
+        counter: u64
+    }
+
+    impl MockRandom {
+        pub fn instantiate() -> Global<MockRandom> {
+            Self {
+                    pending: Vec::new(),
+                    counter: 0
+                }
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .globalize()
+        }
+
+        /// `method(key, random_seed)` is called on the component when the request is fulfilled
+        pub fn request_random(&mut self, component: ComponentAddress, method: String, key: u32) {
+            self.pending.push((component, method, key));
+        }
+
+        /// Call back every pending request
+        pub fn fulfil(&mut self) {
+            let pending: Vec<(ComponentAddress, String, u32)> = self.pending.drain(..).collect();
+            for (component, method, key) in pending {
+              let random_seed = hash(scrypto_encode(&self.counter).unwrap()).0.to_vec();
+              self.counter += 1;
+              let component: Global<AnyComponent> = Global::from(component);
+              component.call::<_, ()>(&method, &(key, random_seed));
+            }
+        }
+    }
+}
@@ -11,12 +11,51 @@ type Actor = (Secp256k1PublicKey, Secp256k1PrivateKey, ComponentAddress);
 struct TestEnv {
     runner: DefaultTestRunner,
     collection: ComponentAddress,
-    owner_badge: ResourceAddress
+    owner_badge: ResourceAddress,
+    nft: ResourceAddress,
+    receipt: ResourceAddress,
+    round: u64,
+    platform: Actor,
+    platform_badge: ResourceAddress,
+    package: PackageAddress
 }
 
 #[derive(ScryptoSbor, NonFungibleData, ManifestSbor)]
 struct EmptyNonFungibleData {}
 
+#[derive(ScryptoSbor, NonFungibleData)]
+struct NFT {
+    name: String,
+    key_image_url: String,
+    traits: Vec<(String, String)>,
+    ipfs_cid: Option<String>
+}
+
+#[derive(ManifestSbor)]
+enum DecayCurve {
+    Linear(Decimal),
+    Exponential(Decimal),
+    Stepped(Vec<(u64, Decimal)>)
+}
+
+#[derive(ManifestSbor)]
+enum BondingCurve {
+    Linear(Decimal, Decimal),
+    Exponential(Decimal, Decimal)
+}
+
+#[derive(ManifestSbor)]
+enum Payee {
+    Account(ComponentAddress),
+    Badge(ResourceAddress)
+}
+
+#[derive(ManifestSbor)]
+enum Discount {
+    Percentage(u16),
+    Fixed(Decimal)
+}
+
 fn create_non_fungible_tokens<'a>(
     runner: &mut DefaultTestRunner,
     owner: &Actor,
@@ -88,10 +127,12 @@ impl TestEnv {
         let nft_addr = create_non_fungible_tokens(&mut runner, &seller, nft_ids.iter());
     
         let buyers: Vec<Actor> = (0..3).map(|_| runner.new_allocated_account()).collect();
+        let platform = runner.new_allocated_account();
+        let platform_badge = create_fungible_tokens(&mut runner, &platform, dec!(1));
         let package = runner.compile_and_publish(this_package!());
         
         let transaction = ManifestBuilder::new()
-            .call_function(package, "NftProject", "instantiate_component", manifest_args!(ccy_addr, "NFT Collection"))
+            .call_function(package, "NftProject", "instantiate_component", manifest_args!(ccy_addr, "NFT Collection", platform_badge, 250u16, dec!(0)))
             .deposit_batch(seller.2)
             .build();
         let receipt = runner.execute_manifest_ignoring_fee(transaction, vec![NonFungibleGlobalId::from_public_key(&seller.0)]);
@@ -100,6 +141,8 @@ impl TestEnv {
         let result = &receipt.expect_commit(true);
         let collection = result.new_component_addresses()[0];
         let owner_badge = result.new_resource_addresses()[0];
+        let nft = result.new_resource_addresses()[1];
+        let receipt = result.new_resource_addresses()[2];
         
         /*
         let mut entries = Vec::new();
@@ -119,6 +162,12 @@ impl TestEnv {
                 runner,
                 owner_badge,
                 collection,
+                nft,
+                receipt,
+                round: 0,
+                platform,
+                platform_badge,
+                package,
             },
             seller,
             buyers,
@@ -150,6 +199,70 @@ impl TestEnv {
         receipt.expect_commit_success();
     }
     
+    fn set_dutch_curve_auction(&mut self, actor: &Actor, initial: Decimal, floor: Decimal, curve: DecayCurve, length: u64, should_fail: bool){
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_auction_dutch_curve", manifest_args!(initial, floor, curve, length))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn set_uniform_price(&mut self, actor: &Actor, enabled: bool, should_fail: bool){
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_uniform_price", manifest_args!(enabled))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn set_sale_window(&mut self, actor: &Actor, start: i64, end: i64){
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_sale_window", manifest_args!(Instant::new(start), Instant::new(end)))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn add_sale_phase(&mut self, actor: &Actor, start: i64, end: i64, price: Option<Decimal>, whitelist: Option<(ResourceAddress, u16)>){
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"add_sale_phase", manifest_args!(
+              Instant::new(start),
+              Instant::new(end),
+              price,
+              whitelist.map(|(addr, _)| addr),
+              whitelist.map(|(_, max)| max)
+            ))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn set_bonding_auction(&mut self, actor: &Actor, curve: BondingCurve){
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_auction_bonding", manifest_args!(curve))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
     fn set_english_auction(&mut self, actor: &Actor, initial: Decimal, length: u64){
         let transaction = ManifestBuilder::new()
             .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
@@ -160,6 +273,15 @@ impl TestEnv {
         receipt.expect_commit_success();
     }
     
+    fn set_discount(&mut self, actor: &Actor, addr: ResourceAddress, ids: Option<Vec<NonFungibleLocalId>>, discount: Discount, max_uses: Option<u16>) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_discount", manifest_args!(addr, ids, discount, max_uses))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
     fn set_whitelist(&mut self, actor: &Actor, addr: ResourceAddress, nb: u16){
         let transaction = ManifestBuilder::new()
             .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
@@ -183,7 +305,241 @@ impl TestEnv {
         result.output(2)
     }
     
+    fn mint_placeholder(&mut self, actor: &Actor) -> NonFungibleLocalId {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"mint_placeholder", manifest_args!("hidden", "placeholder_url", ""))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        let result = receipt.expect_commit_success();
+        result.output(2)
+    }
+    
+    fn set_reveal_commitment(&mut self, actor: &Actor, commitment: Hash) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_reveal_commitment", manifest_args!(commitment))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn reveal(&mut self, actor: &Actor, nfts: Vec<(NonFungibleLocalId, String, String, String, Option<String>, Vec<Hash>)>, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"reveal", manifest_args!(nfts))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn update_key_image_url(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, url: &str, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"update_key_image_url", manifest_args!(id_nft.clone(), url.to_string()))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn update_ipfs_cid(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, cid: &str, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"update_ipfs_cid", manifest_args!(id_nft.clone(), cid.to_string()))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn update_metadata(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, metadata: &str, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"update_metadata", manifest_args!(id_nft.clone(), metadata.to_string()))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn freeze_metadata(&mut self, actor: &Actor, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"freeze_metadata", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn mint_nft_ipfs(&mut self, actor: &Actor, cid: &str, should_fail: bool) -> Option<NonFungibleLocalId> {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"mint_nft_ipfs", manifest_args!("name", cid.to_string(), "color,blue;type,image"))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+          None
+        } else {
+          let result = receipt.expect_commit_success();
+          Some(result.output(2))
+        }
+    }
+    
+    fn set_ipfs_gateway(&mut self, actor: &Actor, gateway: &str, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_ipfs_gateway", manifest_args!(gateway.to_string()))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn mint_nft_with_traits(&mut self, actor: &Actor, traits: Vec<(&str, &str)>, should_fail: bool) -> Option<NonFungibleLocalId> {
+        let traits: Vec<(String, String)> = traits.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"mint_nft_with_traits", manifest_args!("name", "url1", traits))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+          None
+        } else {
+          let result = receipt.expect_commit_success();
+          Some(result.output(2))
+        }
+    }
+    
+    fn compute_rarity_ranks(&mut self, actor: &Actor, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"compute_rarity_ranks", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn get_rarity(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId) -> (Decimal, u64) {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"get_rarity", manifest_args!(id_nft.clone()))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        let result = receipt.expect_commit_success();
+        result.output(1)
+    }
+    
+    fn set_rarity_prices(&mut self, actor: &Actor, prices: Vec<(u64, Decimal)>) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_rarity_prices", manifest_args!(prices))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn mint_nfts(&mut self, actor: &Actor, count: usize, should_fail: bool) -> Vec<NonFungibleLocalId> {
+        let nfts: Vec<(String, String, String)> = (0..count)
+          .map(|i| (format!("name {}", i), format!("url {}", i), "color,blue;type,image".to_string()))
+          .collect();
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"mint_nfts", manifest_args!(nfts))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+          Vec::new()
+        } else {
+          let result = receipt.expect_commit_success();
+          result.output(2)
+        }
+    }
+    
+    fn mint_nfts_with_traits(&mut self, actor: &Actor, count: usize, trait_count: usize, should_fail: bool) -> Vec<NonFungibleLocalId> {
+        let nfts: Vec<(String, String, Vec<(String, String)>)> = (0..count)
+          .map(|i| (
+            format!("name {}", i),
+            format!("url {}", i),
+            (0..trait_count).map(|t| (format!("trait {}", t), format!("value {}", (i + t) % 5))).collect()
+          ))
+          .collect();
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"mint_nfts_with_traits", manifest_args!(nfts))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+          Vec::new()
+        } else {
+          let result = receipt.expect_commit_success();
+          result.output(2)
+        }
+    }
+    
+    fn set_price_tier(&mut self, actor: &Actor, tier: &str, price: Decimal) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_price_tier", manifest_args!(tier.to_string(), price))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn set_nft_price(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, price: Decimal) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_nft_price", manifest_args!(id_nft.clone(), price))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn mint_nft_in_tier(&mut self, actor: &Actor, tier: &str) -> NonFungibleLocalId {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"mint_nft_in_tier", manifest_args!("name", "url1", "color,gold;type,image", tier.to_string()))
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        let result = receipt.expect_commit_success();
+        result.output(2)
+    }
+    
     fn buy_nft(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, amount: Decimal, badge: Option<&(ResourceAddress, NonFungibleLocalId)>, should_fail: bool) {
+        self.buy_nft_referred(actor, id_nft, amount, badge, None, should_fail)
+    }
+    
+    fn buy_nft_referred(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, amount: Decimal, badge: Option<&(ResourceAddress, NonFungibleLocalId)>, referrer: Option<ComponentAddress>, should_fail: bool) {
         let transaction = 
           match badge {
             Option::Some((address, id_whitelist)) => {
@@ -198,7 +554,9 @@ impl TestEnv {
                   |lookup| (
                     id_nft.clone(),
                     lookup.bucket("xrd"),
-                    Some(lookup.bucket("nft"))
+                    Some(lookup.bucket("nft")),
+                    None::<ManifestBucket>,
+                    referrer
                   )
                 )
                 .deposit_batch(actor.2)
@@ -212,7 +570,9 @@ impl TestEnv {
                   |lookup| (
                     id_nft.clone(),
                     lookup.bucket("xrd"),
-                    None::<ManifestBucket>
+                    None::<ManifestBucket>,
+                    None::<ManifestBucket>,
+                    referrer
                   )
                 )
                 .deposit_batch(actor.2)
@@ -228,218 +588,1887 @@ impl TestEnv {
         }
     }
     
-    fn withdraw(&mut self, actor: &Actor, badge: ResourceAddress) {
+    fn buy_nft_with_discount_badge(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, amount: Decimal, badge: &(ResourceAddress, NonFungibleLocalId), discount_badge: &(ResourceAddress, NonFungibleLocalId), should_fail: bool) {
+        let (address, id_whitelist) = badge;
+        let (discount_address, id_discount) = discount_badge;
+        let mut entries = BTreeSet::new();
+        entries.insert(id_whitelist.clone());
+        let mut discount_entries = BTreeSet::new();
+        discount_entries.insert(id_discount.clone());
         let transaction = ManifestBuilder::new()
-            .withdraw_from_account(actor.2, badge, dec!(1))
-            .take_all_from_worktop(badge, "badge")
-            .call_method_with_name_lookup(self.collection,"withdraw",
+            .withdraw_from_account(actor.2, XRD, amount)
+            .withdraw_non_fungibles_from_account(actor.2, *address, entries.clone())
+            .withdraw_non_fungibles_from_account(actor.2, *discount_address, discount_entries.clone())
+            .take_all_from_worktop(XRD, "xrd")
+            .take_non_fungibles_from_worktop(*address, entries, "nft")
+            .take_non_fungibles_from_worktop(*discount_address, discount_entries, "discount")
+            .call_method_with_name_lookup(self.collection,"buy_nft",
               |lookup| (
-                lookup.bucket("badge"),
+                id_nft.clone(),
+                lookup.bucket("xrd"),
+                Some(lookup.bucket("nft")),
+                Some(lookup.bucket("discount")),
+                None::<ComponentAddress>
               )
             )
             .deposit_batch(actor.2)
             .build();
         let receipt = self.execute(transaction, actor);
-        receipt.expect_commit_success();
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
     }
     
-    fn start_auction(&mut self, actor: &Actor) {
-        let transaction = ManifestBuilder::new()
-            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
-            .call_method(self.collection,"start_auction", manifest_args!())
-            .deposit_batch(actor.2)
-            .build();
+    fn buy_nfts(&mut self, actor: &Actor, ids: Vec<NonFungibleLocalId>, amount: Decimal, badge: Option<&(ResourceAddress, NonFungibleLocalId)>, should_fail: bool) {
+        let transaction = 
+          match badge {
+            Option::Some((address, id_whitelist)) => {
+              let mut entries = BTreeSet::new();
+              entries.insert(id_whitelist.clone());
+              ManifestBuilder::new()
+                .withdraw_from_account(actor.2, XRD, amount)
+                .withdraw_non_fungibles_from_account(actor.2, *address, entries.clone())
+                .take_all_from_worktop(XRD, "xrd")
+                .take_non_fungibles_from_worktop(*address, entries, "nft")
+                .call_method_with_name_lookup(self.collection,"buy_nfts",
+                  |lookup| (
+                    ids.clone(),
+                    lookup.bucket("xrd"),
+                    Some(lookup.bucket("nft")),
+                    None::<ManifestBucket>,
+                    None::<ComponentAddress>
+                  )
+                )
+                .deposit_batch(actor.2)
+                .build()
+            },
+            Option::None => {
+              ManifestBuilder::new()
+                .withdraw_from_account(actor.2, XRD, amount)
+                .take_all_from_worktop(XRD, "xrd")
+                .call_method_with_name_lookup(self.collection,"buy_nfts",
+                  |lookup| (
+                    ids.clone(),
+                    lookup.bucket("xrd"),
+                    None::<ManifestBucket>,
+                    None::<ManifestBucket>,
+                    None::<ComponentAddress>
+                  )
+                )
+                .deposit_batch(actor.2)
+                .build()
+            }
+          };
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn buy_nft_in_ccy(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, ccy_addr: ResourceAddress, amount: Decimal, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .withdraw_from_account(actor.2, ccy_addr, amount)
+            .take_all_from_worktop(ccy_addr, "payment")
+            .call_method_with_name_lookup(self.collection,"buy_nft",
+              |lookup| (
+                id_nft.clone(),
+                lookup.bucket("payment"),
+                None::<ManifestBucket>,
+                None::<ManifestBucket>,
+                None::<ComponentAddress>
+              )
+            )
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn set_currency(&mut self, actor: &Actor, ccy_addr: ResourceAddress, rate: Decimal, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_currency", manifest_args!(ccy_addr, rate))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn remove_currency(&mut self, actor: &Actor, ccy_addr: ResourceAddress) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"remove_currency", manifest_args!(ccy_addr))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn instantiate_oracle(&mut self, actor: &Actor) -> ComponentAddress {
+        let transaction = ManifestBuilder::new()
+            .call_function(self.package, "MockOracle", "instantiate", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+        receipt.expect_commit(true).new_component_addresses()[0]
+    }
+    
+    fn set_oracle_price(&mut self, actor: &Actor, oracle: ComponentAddress, ccy_addr: ResourceAddress, price: Decimal) {
+        let transaction = ManifestBuilder::new()
+            .call_method(oracle,"set_price", manifest_args!(ccy_addr, price))
+            .build();
         let receipt = self.execute(transaction, actor);
         receipt.expect_commit_success();
     }
     
-    fn close_auction(&mut self, actor: &Actor) {
+    fn set_oracle(&mut self, actor: &Actor, oracle: ComponentAddress, max_age: i64, max_deviation: Decimal, should_fail: bool) {
         let transaction = ManifestBuilder::new()
             .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
-            .call_method(self.collection,"close_auction", manifest_args!())
+            .call_method(self.collection,"set_oracle", manifest_args!(oracle, max_age, max_deviation))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn instantiate_random(&mut self, actor: &Actor) -> ComponentAddress {
+        let transaction = ManifestBuilder::new()
+            .call_function(self.package, "MockRandom", "instantiate", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+        receipt.expect_commit(true).new_component_addresses()[0]
+    }
+    
+    fn set_random_source(&mut self, actor: &Actor, source: ComponentAddress) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_random_source", manifest_args!(source))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn buy_random(&mut self, actor: &Actor, amount: Decimal, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .withdraw_from_account(actor.2, XRD, amount)
+            .take_all_from_worktop(XRD, "xrd")
+            .call_method_with_name_lookup(self.collection,"buy_random",
+              |lookup| (
+                lookup.bucket("xrd"),
+                None::<ManifestBucket>,
+                None::<ManifestBucket>,
+                actor.2,
+                None::<ComponentAddress>
+              )
+            )
             .deposit_batch(actor.2)
             .build();
         let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn cancel_ticket(&mut self, actor: &Actor, key: u32, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"cancel_ticket", manifest_args!(key))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn fulfil_random(&mut self, actor: &Actor, source: ComponentAddress) {
+        let transaction = ManifestBuilder::new()
+            .call_method(source,"fulfil", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
         receipt.expect_commit_success();
     }
     
-    fn collect_payments(&mut self, actor: &Actor) {
+    fn place_bid(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, amount: Decimal, should_fail: bool) {
+        self.place_bid_with_badge(actor, id_nft, amount, None, should_fail)
+    }
+    
+    fn place_bid_with_badge(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, amount: Decimal, badge: Option<&(ResourceAddress, NonFungibleLocalId)>, should_fail: bool) {
+        let transaction = 
+          match badge {
+            Option::Some((address, id_whitelist)) => {
+              let mut entries = BTreeSet::new();
+              entries.insert(id_whitelist.clone());
+              ManifestBuilder::new()
+                .withdraw_from_account(actor.2, XRD, amount)
+                .withdraw_non_fungibles_from_account(actor.2, *address, entries.clone())
+                .take_all_from_worktop(XRD, "xrd")
+                .take_non_fungibles_from_worktop(*address, entries, "nft")
+                .call_method_with_name_lookup(self.collection,"place_bid",
+                  |lookup| (
+                    id_nft.clone(),
+                    lookup.bucket("xrd"),
+                    actor.2,
+                    Some(lookup.bucket("nft"))
+                  )
+                )
+                .deposit_batch(actor.2)
+                .build()
+            },
+            Option::None => {
+              ManifestBuilder::new()
+                .withdraw_from_account(actor.2, XRD, amount)
+                .take_all_from_worktop(XRD, "xrd")
+                .call_method_with_name_lookup(self.collection,"place_bid",
+                  |lookup| (
+                    id_nft.clone(),
+                    lookup.bucket("xrd"),
+                    actor.2,
+                    None::<ManifestBucket>
+                  )
+                )
+                .deposit_batch(actor.2)
+                .build()
+            }
+          };
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn set_bid_increment(&mut self, actor: &Actor, bid_increment_bps: u16) {
         let transaction = ManifestBuilder::new()
             .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
-            .call_method(self.collection,"collect_payments", manifest_args!())
+            .call_method(self.collection,"set_bid_increment", manifest_args!(bid_increment_bps))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn set_sealed_auction(&mut self, actor: &Actor, reserve: Decimal, commit_length: u64, reveal_length: u64){
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_auction_sealed", manifest_args!(reserve, commit_length, reveal_length))
             .deposit_batch(actor.2)
             .build();
         let receipt = self.execute(transaction, actor);
         receipt.expect_commit_success();
     }
     
-    fn list_present_nft(&mut self, actor: &Actor) {
+    fn set_vickrey_auction(&mut self, actor: &Actor, reserve: Decimal, commit_length: u64, reveal_length: u64){
         let transaction = ManifestBuilder::new()
-            .call_method(self.collection,"list_present_nft", manifest_args!())
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_auction_vickrey", manifest_args!(reserve, commit_length, reveal_length))
+            .deposit_batch(actor.2)
             .build();
         let receipt = self.execute(transaction, actor);
         receipt.expect_commit_success();
     }
     
-    fn set_epoch(&mut self, epoch: u64) {
-        self.runner.set_current_epoch(Epoch::of(epoch));
+    fn commit_bid(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, bid: Decimal, salt: &str, deposit: Decimal, should_fail: bool) {
+        let commitment = hash(scrypto_encode(&(actor.2, id_nft.clone(), bid, salt.to_string())).unwrap());
+        self.commit_bid_hash(actor, id_nft, commitment, deposit, should_fail);
+    }
+    
+    fn commit_bid_hash(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, commitment: Hash, deposit: Decimal, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .withdraw_from_account(actor.2, XRD, deposit)
+            .take_all_from_worktop(XRD, "xrd")
+            .call_method_with_name_lookup(self.collection,"commit_bid",
+              |lookup| (
+                id_nft.clone(),
+                commitment,
+                lookup.bucket("xrd"),
+                actor.2,
+                None::<ManifestBucket>
+              )
+            )
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn reveal_bid(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, bid: Decimal, salt: &str, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"reveal_bid", manifest_args!(id_nft.clone(), actor.2, bid, salt.to_string()))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn settle(&mut self, actor: &Actor, id_nft: &NonFungibleLocalId, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"settle", manifest_args!(id_nft.clone()))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn settle_all(&mut self, actor: &Actor) {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"settle_all", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn claim_rebate(&mut self, actor: &Actor, nb: Decimal, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .withdraw_from_account(actor.2, self.receipt, nb)
+            .take_all_from_worktop(self.receipt, "receipts")
+            .call_method_with_name_lookup(self.collection,"claim_rebate",
+              |lookup| (
+                lookup.bucket("receipts"),
+              )
+            )
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn withdraw(&mut self, actor: &Actor, badge: ResourceAddress) {
+        let transaction = ManifestBuilder::new()
+            .withdraw_from_account(actor.2, badge, dec!(1))
+            .take_all_from_worktop(badge, "badge")
+            .call_method_with_name_lookup(self.collection,"withdraw",
+              |lookup| (
+                lookup.bucket("badge"),
+              )
+            )
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
     }
+    
+    fn start_auction(&mut self, actor: &Actor) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"start_auction", manifest_args!())
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn close_auction(&mut self, actor: &Actor, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"close_auction", manifest_args!())
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn withdraw_unsold(&mut self, actor: &Actor, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"withdraw_unsold", manifest_args!())
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn collect_payments(&mut self, actor: &Actor) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"collect_payments", manifest_args!())
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn collect_fees(&mut self, actor: Option<&Actor>, should_fail: bool) {
+        // Collect as the platform unless another actor is given
+        let (account, badge, key) = match actor {
+          Option::Some(actor) => (actor.2, self.owner_badge, actor.0),
+          Option::None => (self.platform.2, self.platform_badge, self.platform.0)
+        };
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(account, badge, dec!(1))
+            .call_method(self.collection,"collect_fees", manifest_args!())
+            .deposit_batch(account)
+            .build();
+        let receipt = self.runner.execute_manifest_ignoring_fee(transaction, vec![NonFungibleGlobalId::from_public_key(&key)]);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn get_fees_collected(&mut self, actor: &Actor, ccy_addr: ResourceAddress) -> Decimal {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"get_fees_collected", manifest_args!(ccy_addr))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        let result = receipt.expect_commit_success();
+        result.output(1)
+    }
+    
+    fn set_royalty(&mut self, actor: &Actor, royalty_bps: u16, royalty_min: Decimal) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_royalty", manifest_args!(royalty_bps, royalty_min))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn enforce_royalties(&mut self, actor: &Actor) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"enforce_royalties", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn transfer_with_royalty(&mut self, src: &Actor, dest: &Actor, id_nft: &NonFungibleLocalId, amount: Decimal, should_fail: bool) {
+        let mut entries = BTreeSet::new();
+        entries.insert(id_nft.clone());
+        let transaction = ManifestBuilder::new()
+            .withdraw_non_fungibles_from_account(src.2, self.nft, entries.clone())
+            .withdraw_from_account(dest.2, XRD, amount)
+            .take_non_fungibles_from_worktop(self.nft, entries, "nft")
+            .take_all_from_worktop(XRD, "xrd")
+            .call_method_with_name_lookup(self.collection,"transfer_nft",
+              |lookup| (
+                lookup.bucket("nft"),
+                lookup.bucket("xrd"),
+                dest.2
+              )
+            )
+            .deposit_batch(src.2)
+            .build();
+        let receipt = self.runner.execute_manifest_ignoring_fee(transaction, vec![
+          NonFungibleGlobalId::from_public_key(&src.0),
+          NonFungibleGlobalId::from_public_key(&dest.0)
+        ]);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn set_payees(&mut self, actor: &Actor, payees: Vec<(Payee, u16)>) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_payees", manifest_args!(payees))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn claim_balance(&mut self, actor: &Actor, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"claim_balance", manifest_args!(actor.2))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn claim_balance_with_badge(&mut self, actor: &Actor, badge: ResourceAddress, should_fail: bool) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, badge, dec!(1))
+            .pop_from_auth_zone("badge")
+            .call_method_with_name_lookup(self.collection,"claim_balance_with_badge",
+              |lookup| (
+                lookup.proof("badge"),
+              )
+            )
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        if should_fail {
+          receipt.expect_commit_failure();
+        } else {
+          receipt.expect_commit_success();
+        }
+    }
+    
+    fn claim_balance_with_empty_proof(&mut self, actor: &Actor, badge: ResourceAddress) {
+        let transaction = ManifestBuilder::new()
+            .take_from_worktop(badge, dec!(0), "empty")
+            .create_proof_from_bucket_of_all("empty", "badge")
+            .call_method_with_name_lookup(self.collection,"claim_balance_with_badge",
+              |lookup| (
+                lookup.proof("badge"),
+              )
+            )
+            .return_to_worktop("empty")
+            .deposit_batch(actor.2)
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_failure();
+    }
+    
+    fn get_balance(&mut self, actor: &Actor, payee: Payee, ccy_addr: ResourceAddress) -> Decimal {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"get_balance", manifest_args!(payee, ccy_addr))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        let result = receipt.expect_commit_success();
+        result.output(1)
+    }
+    
+    fn add_referrer(&mut self, actor: &Actor, account: ComponentAddress) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"add_referrer", manifest_args!(account))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn set_referral_commission(&mut self, actor: &Actor, referral_bps: u16) {
+        let transaction = ManifestBuilder::new()
+            .create_proof_from_account_of_amount(actor.2, self.owner_badge, dec!(1))
+            .call_method(self.collection,"set_referral_commission", manifest_args!(referral_bps))
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn list_present_nft(&mut self, actor: &Actor) {
+        let transaction = ManifestBuilder::new()
+            .call_method(self.collection,"list_present_nft", manifest_args!())
+            .build();
+        let receipt = self.execute(transaction, actor);
+        receipt.expect_commit_success();
+    }
+    
+    fn set_epoch(&mut self, epoch: u64) {
+        self.runner.set_current_epoch(Epoch::of(epoch));
+    }
+    
+    fn set_time(&mut self, seconds: i64) {
+        self.round += 1;
+        self.runner.advance_to_round_at_timestamp(Round::of(self.round), seconds * 1000);
+    }
+}
+
+#[test]
+fn test_creation() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+}
+
+#[test]
+fn test_withdraw() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    
+    env.buy_nft(&buyers[0], &id, dec!(100), None, false);
+    
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy_wrong_ccy() {
+    let (mut env, owner, buyers, _) = TestEnv::new(Some(dec!(1000)));
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    
+    env.buy_nft(&buyers[0], &id, dec!(100), None, true);
+    
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy_whitelist_fail() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    env.set_whitelist(&owner, addr, 1);
+    env.start_auction(&owner);
+    
+    env.buy_nft(&buyers[0], &id, dec!(100), None, true);
+    
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy_whitelist_succeed() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
+    env.set_whitelist(&owner, addr, 1);
+    env.start_auction(&owner);
+    
+    env.buy_nft(&buyers[0], &id, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy_whitelist_buy_two_succeed() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
+    env.set_whitelist(&owner, addr, 2);
+    env.start_auction(&owner);
+    
+    env.buy_nft(&buyers[0], &id, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    env.buy_nft(&buyers[0], &id2, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy_whitelist_buy_two_fail() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
+    env.set_whitelist(&owner, addr, 1);
+    env.start_auction(&owner);
+    
+    env.buy_nft(&buyers[0], &id, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    env.buy_nft(&buyers[0], &id2, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), true);
+    
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy_fail() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    
+    env.buy_nft(&buyers[0], &id,  dec!(1), None, true);
+    
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_creation_dutch() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.start_auction(&owner);
+}
+
+#[test]
+fn test_dutch_buy_beginning() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.start_auction(&owner);
+    env.set_epoch(0);
+    env.buy_nft(&buyers[0], &id,  dec!(10), None, true);
+    env.buy_nft(&buyers[0], &id,  dec!(11), None, false);
+}
+
+#[test]
+fn test_dutch_buy_end() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.start_auction(&owner);
+    env.set_epoch(25);
+    env.buy_nft(&buyers[0], &id,  dec!("0.99"), None, true);
+    env.buy_nft(&buyers[0], &id,  dec!(1), None, false);
+}
+
+#[test]
+fn test_dutch_buy_middle() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.start_auction(&owner);
+    env.set_epoch(5);
+    env.buy_nft(&buyers[0], &id,  dec!(5), None, true);
+    env.buy_nft(&buyers[0], &id,  dec!(6), None, false);
+}
+
+#[test]
+fn test_english_buy_fail() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_english_auction(&owner, dec!(10), 10);
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(100), None, true);
+}
+
+#[test]
+fn test_english_bid_increment() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_english_auction(&owner, dec!(10), 10);
+    env.start_auction(&owner);
+    env.place_bid(&buyers[0], &id, dec!(9), true);
+    env.place_bid(&buyers[0], &id, dec!(10), false);
+    env.place_bid(&buyers[1], &id, dec!("10.4"), true);
+    env.place_bid(&buyers[1], &id, dec!("10.5"), false);
+}
+
+#[test]
+fn test_english_custom_bid_increment() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_english_auction(&owner, dec!(10), 10);
+    env.set_bid_increment(&owner, 1000);
+    env.start_auction(&owner);
+    env.place_bid(&buyers[0], &id, dec!(10), false);
+    env.place_bid(&buyers[1], &id, dec!("10.9"), true);
+    env.place_bid(&buyers[1], &id, dec!(11), false);
+}
+
+#[test]
+fn test_english_close() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_english_auction(&owner, dec!(10), 10);
+    env.start_auction(&owner);
+    env.place_bid(&buyers[0], &id, dec!(10), false);
+    // The bidding can't be stopped early to award the current top bid
+    env.close_auction(&owner, true);
+    env.set_epoch(10);
+    env.close_auction(&owner, false);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(1));
+}
+
+#[test]
+fn test_bids_whitelist() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
+    env.set_epoch(0);
+    env.set_english_auction(&owner, dec!(10), 10);
+    env.set_whitelist(&owner, addr, 1);
+    env.start_auction(&owner);
+    env.place_bid(&buyers[1], &id, dec!(10), true);
+    env.place_bid_with_badge(&buyers[0], &id, dec!(10), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    // Bids don't use the whitelist cap
+    env.place_bid_with_badge(&buyers[0], &id, dec!(20), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+}
+
+#[test]
+fn test_english_settle() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_english_auction(&owner, dec!(10), 10);
+    env.start_auction(&owner);
+    let before: Vec<Decimal> = buyers.iter().map(|buyer| env.runner.get_component_balance(buyer.2, XRD)).collect();
+    env.place_bid(&buyers[0], &id, dec!(10), false);
+    env.place_bid(&buyers[1], &id, dec!(20), false);
+    env.settle(&buyers[1], &id, true);
+    env.set_epoch(10);
+    env.place_bid(&buyers[0], &id, dec!(30), true);
+    env.settle(&buyers[1], &id, false);
+    
+    // The top bidder gets the NFT for its bid, the outbid one is refunded
+    assert_eq!(env.runner.get_component_balance(buyers[1].2, env.nft), dec!(1));
+    assert_eq!(before[1] - env.runner.get_component_balance(buyers[1].2, XRD), dec!(20));
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, XRD), before[0]);
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_sealed_reveal_windows() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_sealed_auction(&owner, dec!(5), 10, 10);
+    env.start_auction(&owner);
+    env.commit_bid(&buyers[0], &id, dec!(12), "secret", dec!(20), false);
+    env.reveal_bid(&buyers[0], &id, dec!(12), "secret", true);
+    env.set_epoch(10);
+    env.commit_bid(&buyers[1], &id, dec!(15), "other", dec!(20), true);
+    env.reveal_bid(&buyers[0], &id, dec!(13), "secret", true);
+    env.reveal_bid(&buyers[0], &id, dec!(12), "secret", false);
+    env.settle(&buyers[0], &id, true);
+    env.set_epoch(20);
+    env.settle(&buyers[0], &id, false);
+}
+
+#[test]
+fn test_sealed_highest_wins() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_sealed_auction(&owner, dec!(5), 10, 10);
+    env.start_auction(&owner);
+    env.commit_bid(&buyers[0], &id, dec!(12), "first", dec!(20), false);
+    env.commit_bid(&buyers[1], &id, dec!(30), "second", dec!(20), false);
+    env.commit_bid(&buyers[2], &id, dec!(15), "third", dec!(20), false);
+    env.set_epoch(10);
+    env.reveal_bid(&buyers[0], &id, dec!(12), "first", false);
+    env.reveal_bid(&buyers[1], &id, dec!(30), "second", true);
+    env.reveal_bid(&buyers[2], &id, dec!(15), "third", false);
+    env.set_epoch(20);
+    let before: Vec<Decimal> = buyers.iter().map(|buyer| env.runner.get_component_balance(buyer.2, XRD)).collect();
+    env.settle(&buyers[2], &id, false);
+    
+    // The highest revealed bid wins and pays its bid, the unrevealed one is refunded like the losing one
+    assert_eq!(env.runner.get_component_balance(buyers[2].2, env.nft), dec!(1));
+    assert_eq!(env.runner.get_component_balance(buyers[2].2, XRD) - before[2], dec!(5));
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, XRD) - before[0], dec!(20));
+    assert_eq!(env.runner.get_component_balance(buyers[1].2, XRD) - before[1], dec!(20));
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_sealed_commit_limits() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_sealed_auction(&owner, dec!(5), 10, 10);
+    env.start_auction(&owner);
+    
+    // The deposit must cover the reserve and a bidder has one sealed bid per NFT
+    env.commit_bid(&buyers[0], &id, dec!(4), "low", dec!(4), true);
+    env.commit_bid(&buyers[0], &id, dec!(12), "first", dec!(20), false);
+    env.commit_bid(&buyers[0], &id, dec!(15), "second", dec!(20), true);
+    env.commit_bid(&buyers[1], &id, dec!(15), "second", dec!(20), false);
+    env.set_epoch(10);
+    env.reveal_bid(&buyers[0], &id, dec!(12), "first", false);
+    env.reveal_bid(&buyers[0], &id, dec!(12), "first", true);
+}
+
+#[test]
+fn test_sealed_copied_commitment() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_sealed_auction(&owner, dec!(5), 10, 10);
+    env.start_auction(&owner);
+    env.commit_bid(&buyers[0], &id, dec!(12), "first", dec!(20), false);
+    // The copied commitment is bound to the first bidder, it can't be revealed by the second one
+    let copied = hash(scrypto_encode(&(buyers[0].2, id.clone(), dec!(12), "first".to_string())).unwrap());
+    env.commit_bid_hash(&buyers[1], &id, copied, dec!(20), false);
+    env.set_epoch(10);
+    env.reveal_bid(&buyers[1], &id, dec!(12), "first", true);
+    env.reveal_bid(&buyers[0], &id, dec!(12), "first", false);
+    env.close_auction(&owner, true);
+    env.set_epoch(20);
+    env.close_auction(&owner, false);
+}
+
+#[test]
+fn test_sealed_bids_whitelist() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    env.set_epoch(0);
+    env.set_sealed_auction(&owner, dec!(5), 10, 10);
+    env.set_whitelist(&owner, addr, 1);
+    env.start_auction(&owner);
+    env.commit_bid(&buyers[0], &id, dec!(12), "secret", dec!(20), true);
+}
+
+#[test]
+fn test_vickrey_second_price() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_vickrey_auction(&owner, dec!(5), 10, 10);
+    env.start_auction(&owner);
+    env.commit_bid(&buyers[0], &id, dec!(12), "first", dec!(20), false);
+    env.commit_bid(&buyers[1], &id, dec!(18), "second", dec!(20), false);
+    env.commit_bid(&buyers[2], &id2, dec!(8), "third", dec!(20), false);
+    env.set_epoch(10);
+    env.reveal_bid(&buyers[0], &id, dec!(12), "first", false);
+    env.reveal_bid(&buyers[1], &id, dec!(18), "second", false);
+    env.reveal_bid(&buyers[2], &id2, dec!(8), "third", false);
+    env.set_epoch(20);
+    let before: Vec<Decimal> = buyers.iter().map(|buyer| env.runner.get_component_balance(buyer.2, XRD)).collect();
+    env.settle_all(&owner);
+    env.settle(&owner, &id, true);
+    
+    // The winner pays the second highest bid, or the reserve without one, and gets the rest of its deposit back
+    assert_eq!(env.runner.get_component_balance(buyers[1].2, env.nft), dec!(1));
+    assert_eq!(env.runner.get_component_balance(buyers[1].2, XRD) - before[1], dec!(8));
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, XRD) - before[0], dec!(20));
+    assert_eq!(env.runner.get_component_balance(buyers[2].2, env.nft), dec!(1));
+    assert_eq!(env.runner.get_component_balance(buyers[2].2, XRD) - before[2], dec!(15));
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_dutch_floor_validation() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.set_dutch_curve_auction(&owner, dec!(11), dec!(5), DecayCurve::Linear(dec!(1)), 10, true);
+    env.set_dutch_curve_auction(&owner, dec!(11), dec!(5), DecayCurve::Stepped(vec![(2, dec!(9)), (5, dec!(4))]), 10, true);
+    env.set_dutch_curve_auction(&owner, dec!(11), dec!(5), DecayCurve::Stepped(vec![(5, dec!(9)), (2, dec!(8))]), 10, true);
+    env.set_dutch_curve_auction(&owner, dec!(11), dec!(5), DecayCurve::Exponential(dec!("0.5")), 10, true);
+    env.set_dutch_curve_auction(&owner, dec!(11), dec!(5), DecayCurve::Linear(dec!("0.5")), 10, false);
+}
+
+#[test]
+fn test_dutch_stepped_buy() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_dutch_curve_auction(&owner, dec!(11), dec!(5), DecayCurve::Stepped(vec![(2, dec!(9)), (5, dec!(6))]), 10, false);
+    env.start_auction(&owner);
+    env.set_epoch(4);
+    env.buy_nft(&buyers[0], &id,  dec!("8.99"), None, true);
+    env.buy_nft(&buyers[0], &id,  dec!(9), None, false);
+}
+
+#[test]
+fn test_dutch_exponential_buy() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_dutch_curve_auction(&owner, dec!(16), dec!(1), DecayCurve::Exponential(dec!("0.5")), 4, false);
+    env.start_auction(&owner);
+    env.set_epoch(2);
+    env.buy_nft(&buyers[0], &id,  dec!("3.99"), None, true);
+    env.buy_nft(&buyers[0], &id,  dec!(4), None, false);
+}
+
+#[test]
+fn test_dutch_uniform_rebate() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    env.set_epoch(0);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.set_uniform_price(&owner, true, false);
+    env.start_auction(&owner);
+    let before = [
+      env.runner.get_component_balance(buyers[0].2, XRD),
+      env.runner.get_component_balance(buyers[1].2, XRD)
+    ];
+    env.buy_nft(&buyers[0], &id,  dec!(11), None, false);
+    env.set_epoch(5);
+    env.buy_nft(&buyers[1], &id2,  dec!(6), None, false);
+    env.claim_rebate(&buyers[0], dec!(1), true);
+    env.close_auction(&owner, false);
+    
+    // Both buyers end up paying the clearing price of 6
+    let paid = before[0] - env.runner.get_component_balance(buyers[0].2, XRD);
+    env.claim_rebate(&buyers[0], dec!(1), false);
+    assert_eq!(paid - (before[0] - env.runner.get_component_balance(buyers[0].2, XRD)), dec!(5));
+    assert_eq!(before[0] - env.runner.get_component_balance(buyers[0].2, XRD), dec!(6));
+    env.claim_rebate(&buyers[1], dec!(1), false);
+    assert_eq!(before[1] - env.runner.get_component_balance(buyers[1].2, XRD), dec!(6));
+}
+
+#[test]
+fn test_uniform_price_without_oracle() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    let oracle = env.instantiate_oracle(&owner);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.set_uniform_price(&owner, true, false);
+    env.set_oracle(&owner, oracle, 600, dec!("0.1"), true);
+    env.set_uniform_price(&owner, false, false);
+    env.set_oracle(&owner, oracle, 600, dec!("0.1"), false);
+    env.set_uniform_price(&owner, true, true);
+}
+
+#[test]
+fn test_uniform_price_requires_dutch() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_uniform_price(&owner, true, true);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.set_uniform_price(&owner, true, false);
+    let transaction = ManifestBuilder::new()
+        .create_proof_from_account_of_amount(owner.2, env.owner_badge, dec!(1))
+        .call_method(env.collection,"set_auction_bonding", manifest_args!(BondingCurve::Linear(dec!(10), dec!(1))))
+        .build();
+    let receipt = env.execute(transaction, &owner);
+    receipt.expect_commit_failure();
+    env.start_auction(&owner);
+}
+
+#[test]
+fn test_sale_window_requires_auction_type() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    let transaction = ManifestBuilder::new()
+        .create_proof_from_account_of_amount(owner.2, env.owner_badge, dec!(1))
+        .call_method(env.collection,"set_sale_window", manifest_args!(Instant::new(6000), Instant::new(12000)))
+        .build();
+    let receipt = env.execute(transaction, &owner);
+    receipt.expect_commit_failure();
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_sale_window(&owner, 6000, 12000);
+}
+
+#[test]
+fn test_sealed_reveal_within_sale_window() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    env.set_sealed_auction(&owner, dec!(5), 60, 60);
+    let transaction = ManifestBuilder::new()
+        .create_proof_from_account_of_amount(owner.2, env.owner_badge, dec!(1))
+        .call_method(env.collection,"set_sale_window", manifest_args!(Instant::new(6000), Instant::new(12000)))
+        .build();
+    let receipt = env.execute(transaction, &owner);
+    receipt.expect_commit_failure();
+    env.set_sealed_auction(&owner, dec!(5), 60, 40);
+    env.set_sale_window(&owner, 6000, 12000);
+    
+    // The windows are checked again when the auction type changes
+    let transaction = ManifestBuilder::new()
+        .create_proof_from_account_of_amount(owner.2, env.owner_badge, dec!(1))
+        .call_method(env.collection,"set_auction_vickrey", manifest_args!(dec!(5), 60u64, 60u64))
+        .build();
+    let receipt = env.execute(transaction, &owner);
+    receipt.expect_commit_failure();
+}
+
+#[test]
+fn test_sale_window() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_sale_window(&owner, 6000, 12000);
+    env.start_auction(&owner);
+    env.set_time(3000);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, true);
+    env.set_time(12000);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, true);
+}
+
+#[test]
+fn test_sale_window_buy() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_sale_window(&owner, 6000, 12000);
+    env.start_auction(&owner);
+    env.set_time(6000);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, false);
+}
+
+#[test]
+fn test_dutch_by_minute() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.set_sale_window(&owner, 6000, 12000);
+    env.start_auction(&owner);
+    env.set_time(6000 + 5 * 60);
+    env.buy_nft(&buyers[0], &id,  dec!(5), None, true);
+    env.buy_nft(&buyers[0], &id,  dec!(6), None, false);
+}
+
+#[test]
+fn test_sale_window_auto_start() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_sale_window(&owner, 6000, 12000);
+    env.set_time(6000);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, false);
+}
+
+#[test]
+fn test_sale_window_auto_close() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.mint_nft(&owner);
+    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    env.set_uniform_price(&owner, true, false);
+    env.set_sale_window(&owner, 6000, 12000);
+    env.set_time(6000);
+    env.withdraw_unsold(&owner, true);
+    env.buy_nft(&buyers[0], &id, dec!(11), None, false);
+    env.set_time(12000);
+    env.withdraw_unsold(&owner, false);
+    env.collect_payments(&owner);
+    env.claim_rebate(&buyers[0], dec!(1), false);
+}
+
+#[test]
+fn test_sale_phases() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    let id3 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
+    env.add_sale_phase(&owner, 6000, 9000, Some(dec!(5)), Some((addr, 1)));
+    env.add_sale_phase(&owner, 9000, 12000, None, None);
+    env.set_time(6000);
+    env.buy_nft(&buyers[1], &id, dec!(5), None, true);
+    env.buy_nft(&buyers[0], &id, dec!(5), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    env.buy_nft(&buyers[0], &id2, dec!(5), Some(&(addr, NonFungibleLocalId::integer(1))), true);
+    env.set_time(9000);
+    env.buy_nft(&buyers[1], &id2, dec!(5), None, true);
+    env.buy_nft(&buyers[1], &id2, dec!(10), None, false);
+    env.set_time(12000);
+    env.buy_nft(&buyers[1], &id3, dec!(10), None, true);
+}
+
+#[test]
+fn test_buy_tier_price() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    env.set_price_tier(&owner, "legendary", dec!(50));
+    let id = env.mint_nft_in_tier(&owner, "legendary");
+    let id2 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, true);
+    env.buy_nft(&buyers[0], &id, dec!(50), None, false);
+    env.buy_nft(&buyers[0], &id2, dec!(10), None, false);
 }
 
 #[test]
-fn test_creation() {
-    let (mut env, owner, _, _) = TestEnv::new(None);
-    env.mint_nft(&owner);
+fn test_buy_nft_price() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    env.set_price_tier(&owner, "legendary", dec!(50));
+    let id = env.mint_nft_in_tier(&owner, "legendary");
+    env.set_nft_price(&owner, &id, dec!(20));
     env.set_fixed_auction(&owner, dec!(10));
     env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(19), None, true);
+    env.buy_nft(&buyers[0], &id, dec!(20), None, false);
 }
 
 #[test]
-fn test_withdraw() {
-    let (mut env, owner, _, _) = TestEnv::new(None);
-    env.collect_payments(&owner);
+fn test_bonding_linear() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    env.set_bonding_auction(&owner, BondingCurve::Linear(dec!(10), dec!(5)));
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, false);
+    env.buy_nft(&buyers[1], &id2, dec!(14), None, true);
+    env.buy_nft(&buyers[1], &id2, dec!(15), None, false);
 }
 
 #[test]
-fn test_buy() {
+fn test_bonding_exponential() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
     let id = env.mint_nft(&owner);
-    env.set_fixed_auction(&owner, dec!(10));
+    let id2 = env.mint_nft(&owner);
+    let id3 = env.mint_nft(&owner);
+    env.set_bonding_auction(&owner, BondingCurve::Exponential(dec!(10), dec!(1)));
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, false);
+    env.buy_nft(&buyers[0], &id2, dec!(20), None, false);
+    env.buy_nft(&buyers[1], &id3, dec!(39), None, true);
+    env.buy_nft(&buyers[1], &id3, dec!(40), None, false);
+}
+
+#[test]
+fn test_platform_fees() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(100));
     env.start_auction(&owner);
-    
     env.buy_nft(&buyers[0], &id, dec!(100), None, false);
-    
-    env.collect_payments(&owner);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("2.5"));
+    env.collect_fees(Some(&owner), true);
+    env.collect_fees(None, false);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!(0));
 }
 
 #[test]
-fn test_buy_wrong_ccy() {
-    let (mut env, owner, buyers, _) = TestEnv::new(Some(dec!(1000)));
+fn test_royalties_enforced() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
     let id = env.mint_nft(&owner);
     env.set_fixed_auction(&owner, dec!(10));
+    env.set_royalty(&owner, 500, dec!(2));
     env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(10), None, false);
+    env.close_auction(&owner, false);
+    env.enforce_royalties(&owner);
     
-    env.buy_nft(&buyers[0], &id, dec!(100), None, true);
+    let mut entries = BTreeSet::new();
+    entries.insert(id.clone());
+    let transaction = ManifestBuilder::new()
+        .withdraw_non_fungibles_from_account(buyers[0].2, env.nft, entries)
+        .deposit_batch(buyers[1].2)
+        .build();
+    let receipt = env.runner.execute_manifest_ignoring_fee(transaction, vec![
+      NonFungibleGlobalId::from_public_key(&buyers[0].0),
+      NonFungibleGlobalId::from_public_key(&buyers[1].0)
+    ]);
+    receipt.expect_commit_failure();
     
+    env.transfer_with_royalty(&buyers[0], &buyers[1], &id, dec!(0), true);
+    env.transfer_with_royalty(&buyers[0], &buyers[1], &id, dec!("1.5"), true);
+    env.transfer_with_royalty(&buyers[0], &buyers[1], &id, dec!(100), false);
+    let before = env.runner.get_component_balance(buyers[1].2, XRD);
+    env.transfer_with_royalty(&buyers[1], &buyers[2], &id, dec!(10), false);
+    assert_eq!(env.runner.get_component_balance(buyers[1].2, XRD) - before, dec!(8));
+}
+
+#[test]
+fn test_payees_split() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(100));
+    let badge = create_fungible_tokens(&mut env.runner, &owner, dec!(1));
+    env.set_payees(&owner, vec![(Payee::Account(buyers[2].2), 1), (Payee::Badge(badge), 3)]);
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(100), None, false);
+    assert_eq!(env.get_balance(&owner, Payee::Account(buyers[2].2), XRD), dec!("24.375"));
+    assert_eq!(env.get_balance(&owner, Payee::Badge(badge), XRD), dec!("73.125"));
+    env.claim_balance(&buyers[2], false);
+    env.claim_balance(&buyers[2], true);
+    env.claim_balance_with_empty_proof(&buyers[1], badge);
+    assert_eq!(env.get_balance(&owner, Payee::Badge(badge), XRD), dec!("73.125"));
+    env.claim_balance_with_badge(&owner, badge, false);
+    env.claim_balance_with_badge(&owner, badge, true);
     env.collect_payments(&owner);
 }
 
 #[test]
-fn test_buy_whitelist_fail() {
+fn test_referral_commission() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
     let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(100));
+    env.set_referral_commission(&owner, 1000);
+    env.start_auction(&owner);
+    env.buy_nft_referred(&buyers[0], &id, dec!(100), None, Some(buyers[1].2), true);
+    env.add_referrer(&owner, buyers[1].2);
+    env.buy_nft_referred(&buyers[0], &id, dec!(100), None, Some(buyers[1].2), false);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(1));
+    assert_eq!(env.get_balance(&owner, Payee::Account(buyers[1].2), XRD), dec!("9.75"));
+    env.claim_balance(&buyers[1], false);
+    env.collect_payments(&owner);
+}
+
+#[test]
+fn test_buy_other_ccy() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id1 = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    let ccy = create_fungible_tokens(&mut env.runner, &buyers[0], dec!(1000));
     env.set_fixed_auction(&owner, dec!(10));
-    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
-    env.set_whitelist(&owner, addr, 1);
+    env.set_currency(&owner, XRD, dec!(2), true);
+    env.set_currency(&owner, ccy, dec!(3), false);
     env.start_auction(&owner);
-    
-    env.buy_nft(&buyers[0], &id, dec!(100), None, true);
-    
+    env.buy_nft_in_ccy(&buyers[0], &id1, ccy, dec!(100), false);
+    env.buy_nft(&buyers[1], &id2, dec!(100), None, false);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, ccy), dec!(970));
+    assert_eq!(env.get_fees_collected(&owner, ccy), dec!("0.75"));
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("0.25"));
     env.collect_payments(&owner);
+    assert_eq!(env.runner.get_component_balance(owner.2, ccy), dec!("29.25"));
 }
 
 #[test]
-fn test_buy_whitelist_succeed() {
+fn test_remove_ccy() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let ccy = create_fungible_tokens(&mut env.runner, &buyers[0], dec!(1000));
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_currency(&owner, ccy, dec!(3), false);
+    env.remove_currency(&owner, ccy);
+    env.start_auction(&owner);
+    env.buy_nft_in_ccy(&buyers[0], &id, ccy, dec!(100), true);
+    env.buy_nft(&buyers[0], &id, dec!(100), None, false);
+}
+
+#[test]
+fn test_oracle_price() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
     let id = env.mint_nft(&owner);
     env.set_fixed_auction(&owner, dec!(10));
+    let oracle = env.instantiate_oracle(&owner);
+    env.set_oracle_price(&owner, oracle, XRD, dec!("0.5"));
+    env.set_oracle(&owner, oracle, 600, dec!("0.1"), false);
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(100), None, false);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("0.5"));
+}
+
+#[test]
+fn test_oracle_stale() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let oracle = env.instantiate_oracle(&owner);
+    env.set_oracle_price(&owner, oracle, XRD, dec!("0.5"));
+    env.set_oracle(&owner, oracle, 600, dec!("0.1"), false);
+    env.start_auction(&owner);
+    env.set_time(3600);
+    env.buy_nft(&buyers[0], &id, dec!(100), None, true);
+    env.set_oracle_price(&owner, oracle, XRD, dec!("0.5"));
+    env.buy_nft(&buyers[0], &id, dec!(100), None, false);
+}
+
+#[test]
+fn test_oracle_deviation() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id1 = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let oracle = env.instantiate_oracle(&owner);
+    env.set_oracle_price(&owner, oracle, XRD, dec!("0.5"));
+    env.set_oracle(&owner, oracle, 600, dec!("0.1"), false);
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id1, dec!(100), None, false);
+    env.set_oracle_price(&owner, oracle, XRD, dec!("0.6"));
+    env.buy_nft(&buyers[1], &id2, dec!(100), None, true);
+    env.set_oracle_price(&owner, oracle, XRD, dec!("0.54"));
+    env.buy_nft(&buyers[1], &id2, dec!(100), None, false);
+}
+
+#[test]
+fn test_discount_percentage() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id1 = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    let id3 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(100));
     let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
     transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
-    env.set_whitelist(&owner, addr, 1);
+    env.set_discount(&owner, addr, None, Discount::Percentage(2000), Some(1));
     env.start_auction(&owner);
+    let badge = (addr, NonFungibleLocalId::integer(1));
+    env.buy_nft(&buyers[0], &id1, dec!(100), Some(&badge), false);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!(2));
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, addr), dec!(1));
+    env.buy_nft(&buyers[0], &id2, dec!(100), Some(&badge), true);
+    env.buy_nft(&buyers[0], &id3, dec!(100), None, false);
+}
+
+#[test]
+fn test_discount_badge_with_whitelist() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id1 = env.mint_nft(&owner);
+    let id2 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(100));
+    let whitelist = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    let discount = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    transfert_nft(&mut env.runner, whitelist, [1,2].iter(), &owner, &buyers[0]);
+    transfert_nft(&mut env.runner, discount, [1].iter(), &owner, &buyers[0]);
+    env.set_whitelist(&owner, whitelist, 2);
+    env.set_discount(&owner, discount, None, Discount::Percentage(2000), None);
+    env.start_auction(&owner);
+    env.buy_nft_with_discount_badge(&buyers[0], &id1, dec!(100), &(whitelist, NonFungibleLocalId::integer(1)), &(discount, NonFungibleLocalId::integer(1)), false);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!(2));
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, whitelist), dec!(2));
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, discount), dec!(1));
     
-    env.buy_nft(&buyers[0], &id, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
-    
-    env.collect_payments(&owner);
+    // Only one whitelist badge can be presented at once
+    let transaction = ManifestBuilder::new()
+        .withdraw_from_account(buyers[0].2, XRD, dec!(100))
+        .withdraw_from_account(buyers[0].2, whitelist, dec!(2))
+        .take_all_from_worktop(XRD, "xrd")
+        .take_all_from_worktop(whitelist, "nft")
+        .call_method_with_name_lookup(env.collection,"buy_nft",
+          |lookup| (
+            id2.clone(),
+            lookup.bucket("xrd"),
+            Some(lookup.bucket("nft")),
+            None::<ManifestBucket>,
+            None::<ComponentAddress>
+          )
+        )
+        .deposit_batch(buyers[0].2)
+        .build();
+    let receipt = env.execute(transaction, &buyers[0]);
+    receipt.expect_commit_failure();
 }
 
 #[test]
-fn test_buy_whitelist_buy_two_succeed() {
+fn test_discount_specific_ids() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
-    let id = env.mint_nft(&owner);
+    let id1 = env.mint_nft(&owner);
     let id2 = env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(100));
+    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
+    transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
+    transfert_nft(&mut env.runner, addr, [2].iter(), &owner, &buyers[1]);
+    env.set_discount(&owner, addr, Some(vec![NonFungibleLocalId::integer(2)]), Discount::Fixed(dec!(30)), None);
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id1, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("2.5"));
+    env.buy_nft(&buyers[1], &id2, dec!(100), Some(&(addr, NonFungibleLocalId::integer(2))), false);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("4.25"));
+}
+
+#[test]
+fn test_buy_nfts() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let ids: Vec<NonFungibleLocalId> = (0..3).map(|_| env.mint_nft(&owner)).collect();
+    env.set_bonding_auction(&owner, BondingCurve::Linear(dec!(10), dec!(10)));
+    env.start_auction(&owner);
+    env.buy_nfts(&buyers[0], ids, dec!(100), None, false);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(3));
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("1.5"));
+}
+
+#[test]
+fn test_buy_nfts_whitelist_cap() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let ids: Vec<NonFungibleLocalId> = (0..3).map(|_| env.mint_nft(&owner)).collect();
     env.set_fixed_auction(&owner, dec!(10));
     let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
     transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
     env.set_whitelist(&owner, addr, 2);
     env.start_auction(&owner);
+    let badge = (addr, NonFungibleLocalId::integer(1));
+    env.buy_nfts(&buyers[0], ids.clone(), dec!(100), Some(&badge), true);
+    env.buy_nfts(&buyers[0], ids[0..2].to_vec(), dec!(100), Some(&badge), false);
+    env.buy_nfts(&buyers[0], ids[2..3].to_vec(), dec!(100), Some(&badge), true);
+}
+
+#[test]
+fn test_buy_nfts_atomic() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let ids: Vec<NonFungibleLocalId> = (0..3).map(|_| env.mint_nft(&owner)).collect();
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[1], &ids[1], dec!(100), None, false);
+    env.buy_nfts(&buyers[0], ids.clone(), dec!(100), None, true);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(0));
+    env.buy_nfts(&buyers[0], vec![ids[0].clone(), ids[2].clone()], dec!(100), None, false);
+}
+
+#[test]
+fn test_buy_random() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    for _ in 0..3 {
+      env.mint_nft(&owner);
+    }
+    env.set_fixed_auction(&owner, dec!(10));
+    let source = env.instantiate_random(&owner);
+    env.set_random_source(&owner, source);
+    env.start_auction(&owner);
+    env.buy_random(&buyers[0], dec!(100), false);
+    env.buy_random(&buyers[1], dec!(100), false);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(0));
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!(0));
+    env.fulfil_random(&owner, source);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(1));
+    assert_eq!(env.runner.get_component_balance(buyers[1].2, env.nft), dec!(1));
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("0.5"));
+}
+
+#[test]
+fn test_buy_random_source_changed() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let source = env.instantiate_random(&owner);
+    env.set_random_source(&owner, source);
+    env.start_auction(&owner);
+    env.buy_random(&buyers[0], dec!(100), false);
     
-    env.buy_nft(&buyers[0], &id, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
-    env.buy_nft(&buyers[0], &id2, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
+    // The pending purchase is still drawn by the source it was requested from
+    let other = env.instantiate_random(&owner);
+    env.set_random_source(&owner, other);
+    env.fulfil_random(&owner, source);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(1));
+}
+
+#[test]
+fn test_cancel_stale_ticket() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    env.mint_nft(&owner);
+    env.set_fixed_auction(&owner, dec!(10));
+    let source = env.instantiate_random(&owner);
+    env.set_random_source(&owner, source);
+    env.start_auction(&owner);
+    env.set_time(0);
+    let before = env.runner.get_component_balance(buyers[0].2, XRD);
+    env.buy_random(&buyers[0], dec!(100), false);
+    env.cancel_ticket(&buyers[0], 0, true);
+    env.set_time(3600);
+    env.cancel_ticket(&buyers[1], 0, false);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, XRD), before);
     
-    env.collect_payments(&owner);
+    // The NFT isn't reserved anymore and can be taken back once the auction is closed
+    env.close_auction(&owner, false);
+    env.withdraw_unsold(&owner, false);
 }
 
 #[test]
-fn test_buy_whitelist_buy_two_fail() {
+fn test_buy_random_reserved() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
     let id = env.mint_nft(&owner);
-    let id2 = env.mint_nft(&owner);
     env.set_fixed_auction(&owner, dec!(10));
-    let addr = create_non_fungible_tokens(&mut env.runner, &owner, [1,2,3].iter());
-    transfert_nft(&mut env.runner, addr, [1].iter(), &owner, &buyers[0]);
-    env.set_whitelist(&owner, addr, 1);
+    let source = env.instantiate_random(&owner);
+    env.set_random_source(&owner, source);
     env.start_auction(&owner);
+    env.buy_random(&buyers[0], dec!(100), false);
+    env.buy_random(&buyers[1], dec!(100), true);
+    env.buy_nft(&buyers[1], &id, dec!(100), None, true);
     
-    env.buy_nft(&buyers[0], &id, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), false);
-    env.buy_nft(&buyers[0], &id2, dec!(100), Some(&(addr, NonFungibleLocalId::integer(1))), true);
+    // Only the random source can draw the NFT
+    let transaction = ManifestBuilder::new()
+        .call_method(env.collection,"fulfil_random", manifest_args!(0u32, vec![0u8; 8]))
+        .build();
+    let receipt = env.execute(transaction, &buyers[1]);
+    receipt.expect_commit_failure();
     
-    env.collect_payments(&owner);
+    env.fulfil_random(&owner, source);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(1));
+}
+
+fn revealed_data(id: &NonFungibleLocalId) -> (NonFungibleLocalId, String, String, String) {
+    (id.clone(), format!("name {}", id), format!("url {}", id), "color,blue".to_string())
+}
+
+fn merkle_pair(a: Hash, b: Hash) -> Hash {
+    if a.0 <= b.0 {
+      hash(scrypto_encode(&(a, b)).unwrap())
+    } else {
+      hash(scrypto_encode(&(b, a)).unwrap())
+    }
+}
+
+/// Merkle root of the revealed data and the data with the proof of each NFT, an odd node is carried to the next level
+fn reveal_tree(nfts: &Vec<(NonFungibleLocalId, String, String, String)>) -> (Hash, Vec<(NonFungibleLocalId, String, String, String, Option<String>, Vec<Hash>)>) {
+    let mut level: Vec<Hash> = nfts.iter()
+      .map(|(id, name, url, metadata)| hash(scrypto_encode(&(id, name, url, metadata)).unwrap()))
+      .collect();
+    let mut positions: Vec<usize> = (0..nfts.len()).collect();
+    let mut proofs: Vec<Vec<Hash>> = vec![Vec::new(); nfts.len()];
+    while level.len() > 1 {
+      for (leaf, position) in positions.iter_mut().enumerate() {
+        let sibling = *position ^ 1;
+        if sibling < level.len() {
+          proofs[leaf].push(level[sibling]);
+        }
+        *position /= 2;
+      }
+      level = level.chunks(2)
+        .map(|pair| if pair.len() == 2 { merkle_pair(pair[0], pair[1]) } else { pair[0] })
+        .collect();
+    }
+    let revealed = nfts.iter().zip(proofs)
+      .map(|((id, name, url, metadata), proof)| (id.clone(), name.clone(), url.clone(), metadata.clone(), None, proof))
+      .collect();
+    (level[0], revealed)
 }
 
 #[test]
-fn test_buy_fail() {
+fn test_reveal() {
+    let (mut env, owner, buyers, _) = TestEnv::new(None);
+    let id1 = env.mint_placeholder(&owner);
+    let id2 = env.mint_placeholder(&owner);
+    let id3 = env.mint_placeholder(&owner);
+    let (root, nfts) = reveal_tree(&vec![revealed_data(&id1), revealed_data(&id2), revealed_data(&id3)]);
+    env.set_reveal_commitment(&owner, root);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id1, dec!(100), None, false);
+    env.reveal(&owner, nfts[0..1].to_vec(), true);
+    env.close_auction(&owner, false);
+    env.reveal(&owner, nfts[0..1].to_vec(), false);
+    env.reveal(&owner, nfts[0..1].to_vec(), true);
+    env.reveal(&owner, nfts[1..3].to_vec(), false);
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id1.clone());
+    assert_eq!(data.key_image_url, format!("url {}", id1));
+}
+
+#[test]
+fn test_reveal_commitment_mismatch() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    let id1 = env.mint_placeholder(&owner);
+    let id2 = env.mint_placeholder(&owner);
+    let (root, nfts) = reveal_tree(&vec![revealed_data(&id1), revealed_data(&id2)]);
+    env.set_reveal_commitment(&owner, root);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    env.close_auction(&owner, false);
+    
+    // Every NFT is checked, not only the last one revealed
+    let mut tampered = nfts.clone();
+    tampered[0].3 = "color,gold".to_string();
+    env.reveal(&owner, tampered[0..1].to_vec(), true);
+    env.reveal(&owner, nfts[1..2].to_vec(), false);
+    env.reveal(&owner, tampered[0..1].to_vec(), true);
+    env.reveal(&owner, nfts[0..1].to_vec(), false);
+}
+
+#[test]
+fn test_update_metadata() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
     let id = env.mint_nft(&owner);
     env.set_fixed_auction(&owner, dec!(10));
     env.start_auction(&owner);
+    env.buy_nft(&buyers[0], &id, dec!(100), None, false);
+    env.update_key_image_url(&owner, &id, "new_url", false);
+    env.update_metadata(&owner, &id, "color,red", false);
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id.clone());
+    assert_eq!(data.key_image_url, "new_url");
+    assert_eq!(data.traits, vec![("color".to_string(), "red".to_string())]);
+    env.update_metadata(&buyers[0], &id, "color,green", true);
     
-    env.buy_nft(&buyers[0], &id,  dec!(1), None, true);
+    // Placeholders only get their data through the reveal
+    let placeholder = env.mint_placeholder(&owner);
+    env.update_metadata(&owner, &placeholder, "color,red", true);
+    env.update_key_image_url(&owner, &placeholder, "new_url", true);
+}
+
+#[test]
+fn test_freeze_metadata() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    let placeholder = env.mint_placeholder(&owner);
+    env.freeze_metadata(&owner, true);
+    env.set_fixed_auction(&owner, dec!(10));
+    env.start_auction(&owner);
+    env.close_auction(&owner, false);
+    let (_, nfts) = reveal_tree(&vec![revealed_data(&placeholder)]);
+    env.reveal(&owner, nfts, false);
+    env.freeze_metadata(&owner, false);
+    env.freeze_metadata(&owner, true);
+    env.update_key_image_url(&owner, &id, "new_url", true);
+}
+
+#[test]
+fn test_mint_nft_ipfs() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    let cid_v0 = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    let cid_v1 = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+    let id = env.mint_nft_ipfs(&owner, cid_v0, false).unwrap();
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id);
+    assert_eq!(data.key_image_url, format!("ipfs://{}", cid_v0));
+    assert_eq!(data.ipfs_cid, Some(cid_v0.to_string()));
+    env.mint_nft_ipfs(&owner, cid_v1, false);
+    env.mint_nft_ipfs(&owner, "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbd0", true);
+    env.mint_nft_ipfs(&owner, "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdI", true);
+    env.mint_nft_ipfs(&owner, "https://example.com/image.png", true);
+}
+
+#[test]
+fn test_update_ipfs_cid() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    let cid_v0 = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    let cid_v1 = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+    let id = env.mint_nft_ipfs(&owner, cid_v0, false).unwrap();
+    env.update_ipfs_cid(&owner, &id, "https://example.com/image.png", true);
+    env.update_ipfs_cid(&owner, &id, cid_v1, false);
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id.clone());
+    assert_eq!(data.key_image_url, format!("ipfs://{}", cid_v1));
+    assert_eq!(data.ipfs_cid, Some(cid_v1.to_string()));
     
-    env.collect_payments(&owner);
+    // An image out of IPFS has no CID anymore
+    env.update_key_image_url(&owner, &id, "https://example.com/image.png", false);
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id);
+    assert_eq!(data.ipfs_cid, None);
 }
 
 #[test]
-fn test_creation_dutch() {
+fn test_reveal_ipfs() {
     let (mut env, owner, _, _) = TestEnv::new(None);
-    env.mint_nft(&owner);
-    env.set_epoch(0);
-    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    let id = env.mint_placeholder(&owner);
+    let cid = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    let (root, mut nfts) = reveal_tree(&vec![(id.clone(), "name".to_string(), format!("ipfs://{}", cid), "color,blue".to_string())]);
+    env.set_reveal_commitment(&owner, root);
+    env.set_fixed_auction(&owner, dec!(10));
     env.start_auction(&owner);
+    env.close_auction(&owner, false);
+    
+    // The url must be the one of the CID
+    let mut mismatched = nfts.clone();
+    mismatched[0].4 = Some("bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi".to_string());
+    env.reveal(&owner, mismatched, true);
+    nfts[0].4 = Some(cid.to_string());
+    env.reveal(&owner, nfts, false);
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id);
+    assert_eq!(data.ipfs_cid, Some(cid.to_string()));
 }
 
 #[test]
-fn test_dutch_buy_beginning() {
-    let (mut env, owner, buyers, _) = TestEnv::new(None);
+fn test_ipfs_gateway() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.set_ipfs_gateway(&owner, "http://ipfs.io/ipfs/", true);
+    env.set_ipfs_gateway(&owner, "https://ipfs.io/ipfs/", false);
+}
+
+#[test]
+fn test_mint_nft_traits() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
     let id = env.mint_nft(&owner);
-    env.set_epoch(0);
-    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id);
+    assert_eq!(data.traits, vec![("color".to_string(), "blue".to_string()), ("type".to_string(), "image".to_string())]);
+    let id = env.mint_nft_with_traits(&owner, vec![("color", "red"), ("eyes", "laser")], false).unwrap();
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id);
+    assert_eq!(data.traits[1], ("eyes".to_string(), "laser".to_string()));
+    env.mint_nft_with_traits(&owner, vec![("color", "red"), ("color", "blue")], true);
+    env.mint_nft_with_traits(&owner, vec![("", "red")], true);
+}
+
+#[test]
+fn test_update_traits_legacy_format() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    let id = env.mint_nft(&owner);
+    env.update_metadata(&owner, &id, "color;red", true);
+    env.update_metadata(&owner, &id, "color,red;eyes,laser", false);
+    let data: NFT = env.runner.get_non_fungible_data(env.nft, id);
+    assert_eq!(data.traits.len(), 2);
+}
+
+#[test]
+fn test_rarity() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    let common1 = env.mint_nft_with_traits(&owner, vec![("color", "blue"), ("eyes", "normal")], false).unwrap();
+    let common2 = env.mint_nft_with_traits(&owner, vec![("color", "blue"), ("eyes", "normal")], false).unwrap();
+    let rare = env.mint_nft_with_traits(&owner, vec![("color", "gold"), ("eyes", "normal")], false).unwrap();
+    env.mint_nft_with_traits(&owner, vec![("color", "blue"), ("eyes", "laser")], false).unwrap();
+    env.compute_rarity_ranks(&owner, false);
+    assert_eq!(env.get_rarity(&owner, &rare), (dec!("5.333333333333333333"), 1));
+    assert_eq!(env.get_rarity(&owner, &common1).1, 3);
+    assert_eq!(env.get_rarity(&owner, &common2).1, 3);
+    env.update_metadata(&owner, &common1, "color,gold;eyes,laser", false);
+    env.compute_rarity_ranks(&owner, false);
+    assert_eq!(env.get_rarity(&owner, &common1).1, 1);
+}
+
+#[test]
+fn test_rarity_after_reveal() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft_with_traits(&owner, vec![("color", "blue")], false).unwrap();
+    let placeholder = env.mint_placeholder(&owner);
+    env.compute_rarity_ranks(&owner, false);
+    env.set_fixed_auction(&owner, dec!(10));
     env.start_auction(&owner);
-    env.set_epoch(0);
-    env.buy_nft(&buyers[0], &id,  dec!(10), None, true);
-    env.buy_nft(&buyers[0], &id,  dec!(11), None, false);
+    env.compute_rarity_ranks(&owner, true);
+    env.close_auction(&owner, false);
+    let (_, nfts) = reveal_tree(&vec![revealed_data(&placeholder)]);
+    env.reveal(&owner, nfts, false);
+    
+    // The ranks are only frozen during the sale
+    env.compute_rarity_ranks(&owner, false);
+    assert_eq!(env.get_rarity(&owner, &placeholder), (dec!(1), 1));
 }
 
 #[test]
-fn test_dutch_buy_end() {
+fn test_rarity_prices() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
-    let id = env.mint_nft(&owner);
-    env.set_epoch(0);
-    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    let common = env.mint_nft_with_traits(&owner, vec![("color", "blue")], false).unwrap();
+    env.mint_nft_with_traits(&owner, vec![("color", "blue")], false).unwrap();
+    let rare = env.mint_nft_with_traits(&owner, vec![("color", "gold")], false).unwrap();
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_rarity_prices(&owner, vec![(1, dec!(50))]);
+    let transaction = ManifestBuilder::new()
+        .create_proof_from_account_of_amount(owner.2, env.owner_badge, dec!(1))
+        .call_method(env.collection,"start_auction", manifest_args!())
+        .build();
+    let receipt = env.execute(transaction, &owner);
+    receipt.expect_commit_failure();
+    env.compute_rarity_ranks(&owner, false);
     env.start_auction(&owner);
-    env.set_epoch(25);
-    env.buy_nft(&buyers[0], &id,  dec!("0.99"), None, true);
-    env.buy_nft(&buyers[0], &id,  dec!(1), None, false);
+    env.buy_nft(&buyers[0], &rare, dec!(40), None, true);
+    env.buy_nft(&buyers[0], &rare, dec!(50), None, false);
+    env.buy_nft(&buyers[1], &common, dec!(10), None, false);
+    assert_eq!(env.get_fees_collected(&owner, XRD), dec!("1.5"));
 }
 
 #[test]
-fn test_dutch_buy_middle() {
+fn test_rarity_prices_sale_window() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    env.mint_nft_with_traits(&owner, vec![("color", "blue")], false).unwrap();
+    env.set_fixed_auction(&owner, dec!(10));
+    env.set_rarity_prices(&owner, vec![(1, dec!(50))]);
+    env.compute_rarity_ranks(&owner, false);
+    env.set_sale_window(&owner, 6000, 12000);
+    
+    // The sale starts by itself with the window, the ranks can't become stale anymore
+    env.mint_nft_with_traits(&owner, vec![("color", "gold")], true);
+}
+
+#[test]
+fn test_mint_nfts() {
     let (mut env, owner, buyers, _) = TestEnv::new(None);
-    let id = env.mint_nft(&owner);
-    env.set_epoch(0);
-    env.set_dutch_auction(&owner, dec!(11), dec!(1), 10);
+    let ids = env.mint_nfts(&owner, 10, false);
+    assert_eq!(ids.len(), 10);
+    env.mint_nfts(&owner, 51, true);
+    env.set_fixed_auction(&owner, dec!(10));
     env.start_auction(&owner);
-    env.set_epoch(5);
-    env.buy_nft(&buyers[0], &id,  dec!(5), None, true);
-    env.buy_nft(&buyers[0], &id,  dec!(6), None, false);
+    env.buy_nfts(&buyers[0], ids[0..3].to_vec(), dec!(100), None, false);
+    assert_eq!(env.runner.get_component_balance(buyers[0].2, env.nft), dec!(3));
+}
+
+#[test]
+fn test_mint_full_batch() {
+    let (mut env, owner, _, _) = TestEnv::new(None);
+    // A full batch fits in a transaction with the traits of every NFT copied for the rarity
+    let ids = env.mint_nfts_with_traits(&owner, 50, 8, false);
+    assert_eq!(ids.len(), 50);
+    env.mint_nfts_with_traits(&owner, 51, 8, true);
 }